@@ -0,0 +1,74 @@
+//! Implementation of the `analyze` command.
+//!
+//! The index histograms of a flow cell directory are computed without using the API and written
+//! to a JSON file together with the identity of the flow cell.  The file can be posted later on
+//! using the `upload` command, e.g., from a machine that can connect to the API.
+
+use serde_json;
+use std::fs::File;
+use std::path::Path;
+
+use super::errors::*;
+use ingest;
+use ingest::bcl_data::IndexCounts;
+use settings::Settings;
+
+/// Index histograms of a flow cell directory as written by `analyze` and read by `upload`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexHistograms {
+    /// The vendor ID of the sequencing machine.
+    pub instrument: String,
+    /// The run number.
+    pub run_number: i32,
+    /// The vendor ID of the flow cell.
+    pub flowcell: String,
+    /// Minimal fraction of reads that an index was seen in to be included in the histograms.
+    pub min_index_fraction: f64,
+    /// The histograms for each lane and index read.
+    pub index_counts: Vec<IndexCounts>,
+    /// The joint histograms of the first two index reads for each lane, if analyzed.
+    #[serde(default)]
+    pub index_pair_counts: Vec<IndexCounts>,
+}
+
+/// Main entry point for the `analyze` command.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client analyze");
+    info!(logger, "Options: {:?}", settings);
+
+    let path = Path::new(&settings.analyze.path);
+    if !path.join("RunInfo.xml").exists() {
+        bail!("Path {:?}/RunInfo.xml does not exist!", path);
+    }
+
+    let (folder_layout, run_info, _run_params) = ingest::load_run_folder(logger, path)?;
+    let index_counts =
+        ingest::analyze_index_reads(logger, &run_info, path, folder_layout, settings)?;
+    let index_pair_counts = if settings.ingest.analyze_index_pairs {
+        ingest::analyze_index_pair_reads(logger, &run_info, path, folder_layout, settings)?
+    } else {
+        Vec::new()
+    };
+    let histograms = IndexHistograms {
+        instrument: run_info.instrument,
+        run_number: run_info.run_number,
+        flowcell: run_info.flowcell,
+        min_index_fraction: settings.ingest.min_index_fraction,
+        index_counts,
+        index_pair_counts,
+    };
+
+    info!(
+        logger,
+        "Writing {} index histograms and {} index pair histograms to {}",
+        histograms.index_counts.len(),
+        histograms.index_pair_counts.len(),
+        &settings.analyze.output
+    );
+    let file = File::create(&settings.analyze.output)
+        .chain_err(|| format!("Problem creating {}", &settings.analyze.output))?;
+    serde_json::to_writer_pretty(file, &histograms)
+        .chain_err(|| format!("Problem writing {}", &settings.analyze.output))?;
+
+    Ok(())
+}
@@ -19,9 +19,9 @@ pub struct FlowCell {
     pub num_lanes: i32,
     pub operator: Option<String>,
     pub rta_version: i32,
-    pub status_sequencing: String,
-    pub status_conversion: String,
-    pub status_delivery: String,
+    pub status_sequencing: Status,
+    pub status_conversion: Status,
+    pub status_delivery: Status,
     pub delivery_type: String,
     pub planned_reads: Option<String>,
     pub current_reads: Option<String>,
@@ -79,6 +79,9 @@ pub struct LaneIndexHistogram {
     pub index_read_no: i32,
     pub sample_size: usize,
     pub min_index_fraction: f64,
+    /// Whether only PF clusters were counted.
+    #[serde(default)]
+    pub pf_filtered: bool,
     pub histogram: HashMap<String, usize>,
 }
 
@@ -91,6 +94,23 @@ impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneIndexHistogram {
     }
 }
 
+// Restson arguments: PUT/DELETE LaneIndexHistogram, LaneIndexPairHistogram, and LaneDemuxResult
+// by SODAR UUID.
+pub struct ProjectFlowcellIndexHistoArgs {
+    pub project_uuid: String,
+    pub flowcell_uuid: String,
+    pub indexhisto_uuid: String,
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellIndexHistoArgs> for LaneIndexHistogram {
+    fn get_path(args: &'a ProjectFlowcellIndexHistoArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/indexhistos/{}/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid, &args.indexhisto_uuid
+        ))
+    }
+}
+
 /// Querying index histogram list from DigestiFlow API.
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
@@ -107,6 +127,121 @@ impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneIndexHistogramArray {
     }
 }
 
+/// Joint index pair histogram information for the DigestiFlow API.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LaneIndexPairHistogram {
+    pub sodar_uuid: Option<String>,
+    pub flowcell: String,
+    pub lane: i32,
+    pub sample_size: usize,
+    pub min_index_fraction: f64,
+    /// Whether only PF clusters were counted.
+    #[serde(default)]
+    pub pf_filtered: bool,
+    /// Counts of the index pairs, the sequences of the two index reads joined by `"+"`.
+    pub histogram: HashMap<String, usize>,
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneIndexPairHistogram {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/indexpairhistos/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellIndexHistoArgs> for LaneIndexPairHistogram {
+    fn get_path(args: &'a ProjectFlowcellIndexHistoArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/indexpairhistos/{}/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid, &args.indexhisto_uuid
+        ))
+    }
+}
+
+/// Querying index pair histogram list from DigestiFlow API.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum LaneIndexPairHistogramArray {
+    Array(Vec<LaneIndexPairHistogram>),
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneIndexPairHistogramArray {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/indexpairhistos/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
+/// Demultiplexing statistics of a lane for the DigestiFlow API.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LaneDemuxResult {
+    pub sodar_uuid: Option<String>,
+    pub flowcell: String,
+    pub lane: i32,
+    /// Name of the file the statistics were read from.
+    pub source: String,
+    pub num_reads: u64,
+    pub undetermined_reads: u64,
+    pub yield_bases: Option<u64>,
+    pub samples: Vec<SampleReads>,
+    pub unknown_barcodes: Vec<UnknownBarcode>,
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneDemuxResult {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/demuxstats/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellIndexHistoArgs> for LaneDemuxResult {
+    fn get_path(args: &'a ProjectFlowcellIndexHistoArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/demuxstats/{}/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid, &args.indexhisto_uuid
+        ))
+    }
+}
+
+/// Querying demultiplexing statistics list from DigestiFlow API.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum LaneDemuxResultArray {
+    Array(Vec<LaneDemuxResult>),
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneDemuxResultArray {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/demuxstats/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
+/// Run quality summary from the InterOp files for the DigestiFlow API.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FlowCellRunMetrics {
+    pub flowcell: String,
+    pub highest_extracted_cycle: i32,
+    pub lanes: Vec<LaneRunMetrics>,
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellArgs> for FlowCellRunMetrics {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/runmetrics/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
 /// Adding flow cell message.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FlowCellMessage {
@@ -3,15 +3,17 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use flate2::read::{GzDecoder, MultiGzDecoder};
 use glob::glob;
+use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rand_xorshift;
 use rayon::prelude::*;
 use regex::Regex;
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
+use std::hash::Hash;
 use std::io::prelude::*;
-use std::io::SeekFrom;
+use std::io::{self, BufReader, SeekFrom};
 use std::path::Path;
 
 use super::super::errors::*;
@@ -23,93 +25,193 @@ use settings::Settings;
 pub struct TileBclStack {
     /// The number of the lane that this stack is for.
     pub lane_no: i32,
+    /// The Illumina tile number, e.g., `1101` for surface 1, swath 1, tile 1; `0` if the stack
+    /// covers the whole lane.
+    pub tile_no: i32,
     /// The paths to the BCL files.
     pub paths: Vec<String>,
+    /// The path to the `.filter` file with the PF flags of the clusters, if any.
+    pub filter_path: Option<String>,
+    /// Whether the non-PF clusters have already been excluded from the base call files.
+    pub non_pf_excluded: bool,
 }
 
 /// For a given index read, a histogram of counts (probably cut to top 1% or so).
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct IndexCounts {
-    /// The index of the index.
+    /// The index of the index, `0` for joint index pairs.
     pub index_no: i32,
     /// The index of the lane.
     pub lane_no: i32,
-    /// The number of reads read.
+    /// The number of reads read and counted.
     pub sample_size: usize,
+    /// The number of reads left out because of too many no-calls.
+    pub num_skipped_no_calls: usize,
+    /// Whether only PF clusters were counted for all sampled tiles.
+    pub pf_filtered: bool,
     /// The filtered histogram of read frequencies.
     pub hist: HashMap<String, usize>,
 }
 
-/// Load compressed BCL file.
-fn load_bcl_gz(logger: &slog::Logger, path: &str, settings: &Settings) -> Result<Vec<u8>> {
-    // Open file
-    debug!(logger, "Processing compressed BCL file {}...", &path);
-    let file = File::open(&path).chain_err(|| "Problem opening gzip file")?;
-    let mut gz_decoder = MultiGzDecoder::new(file);
+/// Base code for a no-call in the decoded base calls, `0..4` encode `A`, `C`, `G`, `T`.
+const NO_CALL: u8 = 4;
 
-    // Read number of bytes in file.
-    let num_bytes = gz_decoder
-        .read_u32::<LittleEndian>()
-        .chain_err(|| "Problem reading byte count")? as usize;
+/// Maximal number of cycles that can be packed into a `PackedSeq`, longer index reads are
+/// counted as strings.
+const MAX_PACKED_LEN: usize = 32;
 
-    // Read array with bases and quality values.
-    let num_bytes = if settings.ingest.sample_reads_per_tile > 0 {
-        cmp::min(num_bytes, settings.ingest.sample_reads_per_tile as usize)
-    } else {
-        num_bytes
-    };
-    let mut buf = vec![0u8; num_bytes];
-    gz_decoder
-        .read_exact(&mut buf)
-        .chain_err(|| "Problem reading payload")?;
+/// Maximal number of clusters decoded and counted at once per tile.
+const CHUNK_SIZE: usize = 1 << 16;
 
-    Ok(buf)
+/// A key for counting index sequences in a histogram.
+trait SeqKey: Eq + Hash + Send {
+    /// Build the key for cluster `i` from the base codes of each cycle in `codes`.
+    fn pack(codes: &[Vec<u8>], i: usize) -> Self;
+
+    /// Return the number of no-calls.
+    fn num_no_calls(&self) -> u32;
+
+    /// Convert back to a string of length `len`.
+    fn unpack(&self, len: usize) -> String;
 }
 
-/// Load compressed BCL file.
-fn load_bcl(logger: &slog::Logger, path: &str, settings: &Settings) -> Result<Vec<u8>> {
-    // Open file
-    debug!(logger, "Processing uncompressed BCL file {}...", &path);
-    let mut file = File::open(&path).chain_err(|| "Problem opening BCL file")?;
+/// An index sequence of up to 32 bases packed into 2 bits per base, with a bit mask of the
+/// positions with no-calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PackedSeq {
+    /// The bases, 2 bits per base, first base in the lowest bits; no-calls are stored as `0`.
+    bases: u64,
+    /// Bit `i` is set if base `i` is a no-call.
+    no_calls: u32,
+}
 
-    // Read number of bytes in file.
-    let num_bytes = file
-        .read_u32::<LittleEndian>()
-        .chain_err(|| "Problem reading byte count")? as usize;
+impl SeqKey for PackedSeq {
+    fn pack(codes: &[Vec<u8>], i: usize) -> Self {
+        let mut bases = 0u64;
+        let mut no_calls = 0u32;
+        for (j, cycle) in codes.iter().enumerate() {
+            let code = cycle[i];
+            if code == NO_CALL {
+                no_calls |= 1 << j;
+            } else {
+                bases |= u64::from(code) << (2 * j);
+            }
+        }
+        PackedSeq { bases, no_calls }
+    }
 
-    // Read array with bases and quality values.
-    let num_bytes = if settings.ingest.sample_reads_per_tile > 0 {
-        cmp::min(num_bytes, settings.ingest.sample_reads_per_tile as usize)
-    } else {
-        num_bytes
-    };
-    let mut buf = vec![0u8; num_bytes];
-    file.read_exact(&mut buf)
-        .chain_err(|| "Problem reading payload")?;
+    fn num_no_calls(&self) -> u32 {
+        self.no_calls.count_ones()
+    }
+
+    fn unpack(&self, len: usize) -> String {
+        let table = ['A', 'C', 'G', 'T'];
+        (0..len)
+            .map(|j| {
+                if self.no_calls & (1 << j) != 0 {
+                    'N'
+                } else {
+                    table[((self.bases >> (2 * j)) & 3) as usize]
+                }
+            })
+            .collect()
+    }
+}
+
+/// Fallback for index reads longer than `MAX_PACKED_LEN` cycles, e.g., with UMIs in the index.
+impl SeqKey for String {
+    fn pack(codes: &[Vec<u8>], i: usize) -> Self {
+        let table = ['A', 'C', 'G', 'T', 'N'];
+        codes.iter().map(|cycle| table[cycle[i] as usize]).collect()
+    }
+
+    fn num_no_calls(&self) -> u32 {
+        self.chars().filter(|c| *c == 'N').count() as u32
+    }
+
+    fn unpack(&self, _len: usize) -> String {
+        self.clone()
+    }
+}
+
+/// The counts for one tile, before filtering.
+struct TileCounts<K: SeqKey> {
+    /// The number of reads counted.
+    num_counted: usize,
+    /// The number of reads left out because of too many no-calls.
+    num_skipped_no_calls: usize,
+    /// Whether only PF clusters were counted.
+    pf_filtered: bool,
+    /// The unfiltered histogram.
+    hist: HashMap<K, usize>,
+}
 
-    Ok(buf)
+/// Streaming reader for the PF flags in a `.filter` file.
+struct FilterReader {
+    /// The buffered file, positioned at the flag of the next cluster.
+    reader: BufReader<File>,
+    /// The number of clusters in the file.
+    num_clusters: usize,
+}
+
+impl FilterReader {
+    /// Open the `.filter` file at `path` and read its header.
+    fn open(logger: &slog::Logger, path: &str) -> Result<Self> {
+        debug!(logger, "Processing filter file {}...", &path);
+        let mut reader =
+            BufReader::new(File::open(path).chain_err(|| "Problem opening filter file")?);
+
+        // Newer files start with a zero and the version before the number of clusters.
+        let num_clusters = reader
+            .read_u32::<LittleEndian>()
+            .chain_err(|| "Problem reading filter file header")?;
+        let num_clusters = if num_clusters == 0 {
+            reader
+                .read_u32::<LittleEndian>()
+                .chain_err(|| "Problem reading filter file version")?;
+            reader
+                .read_u32::<LittleEndian>()
+                .chain_err(|| "Problem reading cluster count")?
+        } else {
+            num_clusters
+        } as usize;
+
+        Ok(FilterReader {
+            reader,
+            num_clusters,
+        })
+    }
+
+    /// Read whether the next cluster passes filter.
+    fn next_pf(&mut self) -> Result<bool> {
+        let flag = self
+            .reader
+            .read_u8()
+            .chain_err(|| "Problem reading PF flag")?;
+        Ok(flag & 1 == 1)
+    }
 }
 
 /// Information of q-value mapping.
 struct QValBinInfo {
     /// quality score bin
-    _from: u32,
+    from: u32,
     /// quality score
-    _to: u32,
+    to: u32,
 }
 
 /// Information of offsets in `CBCL` file.
 struct OffsetInfo {
     /// ID of tile
-    _tile_no: u32,
+    tile_no: u32,
     /// Number of clusters on tile
     num_clusters: u32,
     /// Uncompressed size of tile
-    _uncompressed_size: u32,
+    uncompressed_size: u32,
     /// Compressed size of tile
     compressed_size: u32,
     /// 1: non-PF clusters are excluded, 0: non-PF clusters are not excluded.
-    _non_pf_flag: bool,
+    non_pf_flag: bool,
 }
 
 /// Header from a `CBCL` file.
@@ -123,15 +225,14 @@ struct CbclHeader {
     /// Number of bits per q score, digestiflow-cli only supports 6 here
     _bits_per_qscore: u8,
     /// Information of q-value bins
-    _q_val_bins: Vec<QValBinInfo>,
+    q_val_bins: Vec<QValBinInfo>,
     /// Offset information of the gzip files inside the CBCL file.
     offset_infos: Vec<OffsetInfo>,
 }
 
 /// Read CBCL header
 fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
-    let mut file =
-        File::open(&path).chain_err(|| format!("Problem opening CBCL file {}", &path))?;
+    let mut file = File::open(path).chain_err(|| format!("Problem opening CBCL file {}", &path))?;
 
     let version = file
         .read_u16::<LittleEndian>()
@@ -169,10 +270,7 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
         let to = file
             .read_u32::<LittleEndian>()
             .chain_err(|| "Problem reading to.")?;
-        q_val_bins.push(QValBinInfo {
-            _from: from,
-            _to: to,
-        });
+        q_val_bins.push(QValBinInfo { from, to });
     }
 
     let num_offset_infos = file
@@ -195,11 +293,11 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
         let non_pf_flag = file.read_u8().chain_err(|| "Problem reading non pf flag")?;
         let non_pf_flag = non_pf_flag != 0;
         offset_infos.push(OffsetInfo {
-            _tile_no: tile_no,
+            tile_no,
             num_clusters,
-            _uncompressed_size: uncompressed_size,
+            uncompressed_size,
             compressed_size,
-            _non_pf_flag: non_pf_flag,
+            non_pf_flag,
         });
     }
 
@@ -208,53 +306,445 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
         header_size,
         _bits_per_basecall: bits_per_basecall,
         _bits_per_qscore: bits_per_qscore,
-        _q_val_bins: q_val_bins,
+        q_val_bins,
         offset_infos,
     })
 }
 
-/// Read `settings.ingest.sample_reads_per_tile` number of reads from the given tile.
-fn load_from_cbcl(
-    _logger: &slog::Logger,
-    path: &str,
-    header: &CbclHeader,
-    tile_no: u32,
+/// Return the tile numbers listed in the header of the CBCL file at `path`.
+pub fn load_cbcl_tiles(logger: &slog::Logger, path: &str) -> Result<Vec<u32>> {
+    let header = load_cbcl_header(logger, path)?;
+    Ok(header
+        .offset_infos
+        .iter()
+        .map(|info| info.tile_no)
+        .collect())
+}
+
+/// Check that all gzip blocks of the CBCL file at `path` decompress to the size given in its
+/// header.
+pub fn check_cbcl_data(logger: &slog::Logger, path: &str) -> Result<()> {
+    let header = load_cbcl_header(logger, path)?;
+    let mut file = File::open(path).chain_err(|| format!("Problem opening CBCL file {}", path))?;
+
+    let mut offset = header.header_size as u64;
+    for info in &header.offset_infos {
+        file.seek(SeekFrom::Start(offset))
+            .chain_err(|| "Could not jump in CBCL file")?;
+        let block = Read::by_ref(&mut file).take(info.compressed_size as u64);
+        let mut decoder = GzDecoder::new(BufReader::new(block));
+        let size = io::copy(&mut decoder, &mut io::sink())
+            .chain_err(|| format!("Problem decompressing block of tile {}", info.tile_no))?;
+        if size != info.uncompressed_size as u64 {
+            bail!(
+                "Block of tile {} decompressed to {} instead of {} bytes",
+                info.tile_no,
+                size,
+                info.uncompressed_size
+            );
+        }
+        offset += info.compressed_size as u64;
+    }
+
+    let file_size = file
+        .metadata()
+        .chain_err(|| "Problem getting CBCL file size")?
+        .len();
+    if file_size < offset {
+        bail!(
+            "File has {} bytes but header requires {}",
+            file_size,
+            offset
+        );
+    }
+    Ok(())
+}
+
+/// Check that the (compressed) BCL file at `path` can be read completely and contains one byte
+/// for each cluster.
+pub fn check_bcl_data(path: &str) -> Result<()> {
+    let file = File::open(path).chain_err(|| format!("Problem opening BCL file {}", path))?;
+    let mut reader: Box<dyn Read> = if path.ends_with(".gz") || path.ends_with(".bgzf") {
+        Box::new(BufReader::new(MultiGzDecoder::new(BufReader::new(file))))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let num_clusters = reader
+        .read_u32::<LittleEndian>()
+        .chain_err(|| "Problem reading byte count")?;
+    let size = io::copy(&mut reader, &mut io::sink()).chain_err(|| "Problem reading data")?;
+    if size != num_clusters as u64 {
+        bail!("File has {} base calls instead of {}", size, num_clusters);
+    }
+    Ok(())
+}
+
+/// Streaming reader for the base calls of one cycle of a tile.
+enum CycleReader {
+    /// BCL file (plain, gzip or BGZF compressed), one byte per cluster with the base in the lower
+    /// two and the quality in the upper six bits.
+    Bcl(Box<dyn Read + Send>),
+    /// One tile of a CBCL file, one nibble per cluster with the base in the lower two and the
+    /// quality bin in the upper two bits.
+    Cbcl {
+        /// The decoder of the tile's gzip block.
+        decoder: Box<dyn Read + Send>,
+        /// Mapping from quality bin to quality score.
+        q_table: [u32; 4],
+        /// The upper nibble of the last byte read, if not consumed yet.
+        pending: Option<u8>,
+    },
+}
+
+impl CycleReader {
+    /// Open the base call file at `path` and return the reader and the number of clusters.
+    ///
+    /// CBCL tiles are given as `"path/to/file.cbcl!${tile_no}"` where `tile_no` is the tile
+    /// number as listed in the header, e.g., `1101`.
+    fn open(logger: &slog::Logger, path: &str, cbcl_re: &Regex) -> Result<(Self, usize)> {
+        if let Some(captures) = cbcl_re.captures(path) {
+            // Because we know that the RE matches, the following unwrap cannot fail.
+            let cbcl_path = &captures[1];
+            let tile_no = captures[2].parse::<u32>().unwrap();
+            let header =
+                load_cbcl_header(logger, cbcl_path).chain_err(|| "Loading CBL header failed")?;
+
+            // Map the 2-bit quality bins to quality scores.  Bin 0 is the no-call bin.
+            let mut q_table = [0u32, 1, 2, 3];
+            for bin in &header.q_val_bins {
+                if (bin.from as usize) < q_table.len() {
+                    q_table[bin.from as usize] = bin.to;
+                }
+            }
+
+            // Find the offset of the tile's gzip block.
+            let mut offset = header.header_size as u64;
+            let mut tile_info = None;
+            for info in &header.offset_infos {
+                if info.tile_no == tile_no {
+                    tile_info = Some(info);
+                    break;
+                }
+                offset += info.compressed_size as u64;
+            }
+            let num_clusters = match tile_info {
+                Some(info) => info.num_clusters as usize,
+                None => bail!("Tile {} not found in CBCL file {}", tile_no, cbcl_path),
+            };
+
+            debug!(logger, "Processing CBCL tile {}...", &path);
+            let mut file = File::open(cbcl_path)
+                .chain_err(|| format!("Problem opening CBCL file {}", cbcl_path))?;
+            file.seek(SeekFrom::Start(offset))
+                .chain_err(|| "Could not jump in CBCL file")?;
+            let decoder = BufReader::new(GzDecoder::new(BufReader::new(file)));
+
+            Ok((
+                CycleReader::Cbcl {
+                    decoder: Box::new(decoder),
+                    q_table,
+                    pending: None,
+                },
+                num_clusters,
+            ))
+        } else {
+            let file = File::open(path).chain_err(|| "Problem opening BCL file")?;
+            let mut reader: Box<dyn Read + Send> =
+                if path.ends_with(".gz") || path.ends_with(".bgzf") {
+                    debug!(logger, "Processing compressed BCL file {}...", &path);
+                    Box::new(BufReader::new(MultiGzDecoder::new(BufReader::new(file))))
+                } else {
+                    debug!(logger, "Processing uncompressed BCL file {}...", &path);
+                    Box::new(BufReader::new(file))
+                };
+
+            // Read number of clusters in file.
+            let num_clusters = reader
+                .read_u32::<LittleEndian>()
+                .chain_err(|| "Problem reading byte count")?
+                as usize;
+
+            Ok((CycleReader::Bcl(reader), num_clusters))
+        }
+    }
+
+    /// Read the base code of the next cluster, no-calls and bases with a quality below
+    /// `min_quality` are returned as `NO_CALL`.
+    fn next_code(&mut self, min_quality: i32) -> Result<u8> {
+        match *self {
+            CycleReader::Bcl(ref mut reader) => {
+                let b = reader.read_u8().chain_err(|| "Problem reading data byte")?;
+                // No-call if all bits are unset.
+                if b == 0 || ((b >> 2) as i32) < min_quality {
+                    Ok(NO_CALL)
+                } else {
+                    Ok(b & 3)
+                }
+            }
+            CycleReader::Cbcl {
+                ref mut decoder,
+                ref q_table,
+                ref mut pending,
+            } => {
+                let nibble = match pending.take() {
+                    Some(nibble) => nibble,
+                    None => {
+                        let b = decoder
+                            .read_u8()
+                            .chain_err(|| "Problem reading data byte")?;
+                        *pending = Some(b >> 4);
+                        b & 15
+                    }
+                };
+                let q_bin = ((nibble >> 2) & 3) as usize;
+                if q_bin == 0 || (q_table[q_bin] as i32) < min_quality {
+                    Ok(NO_CALL)
+                } else {
+                    Ok(nibble & 3)
+                }
+            }
+        }
+    }
+}
+
+/// The base calls of a chunk of clusters as read by a `StackReader`.
+struct Chunk {
+    /// The base codes of each cycle, one per cluster.
+    codes: Vec<Vec<u8>>,
+    /// Whether the clusters pass filter, if filtering.
+    pf_flags: Option<Vec<bool>>,
+}
+
+/// Reads all cycle files of a stack in lockstep and yields one chunk of clusters at a time.
+///
+/// Only one buffer per cycle file and one chunk of at most `CHUNK_SIZE` clusters are held in
+/// memory, regardless of the number of clusters read.
+struct StackReader {
+    /// The readers for each cycle.
+    cycles: Vec<CycleReader>,
+    /// The reader for the PF flags, if filtering.
+    filter: Option<FilterReader>,
+    /// Minimal base quality, see `IngestArgs::min_base_quality`.
+    min_quality: i32,
+    /// The number of clusters left to read.
+    remaining: usize,
+}
+
+impl StackReader {
+    /// Open all files of `stack`, reading at most `settings.ingest.sample_reads_per_tile`
+    /// clusters.
+    fn open(
+        logger: &slog::Logger,
+        stack: &TileBclStack,
+        cbcl_re: &Regex,
+        settings: &Settings,
+    ) -> Result<Self> {
+        let mut num_clusters = usize::MAX;
+        let mut cycles = Vec::new();
+        for path in &stack.paths {
+            let (reader, cycle_clusters) = CycleReader::open(logger, path, cbcl_re)
+                .chain_err(|| format!("Problem opening base call file {}", path))?;
+            num_clusters = cmp::min(num_clusters, cycle_clusters);
+            cycles.push(reader);
+        }
+
+        // Read PF flags if configured to filter and not already done by the instrument.
+        let filter = match (&stack.filter_path, settings.ingest.filter_pf) {
+            (Some(filter_path), true) if !stack.non_pf_excluded => {
+                let filter = FilterReader::open(logger, filter_path)
+                    .chain_err(|| "Problem loading filter file")?;
+                num_clusters = cmp::min(num_clusters, filter.num_clusters);
+                Some(filter)
+            }
+            _ => None,
+        };
+
+        if cycles.is_empty() {
+            num_clusters = 0;
+        }
+        if settings.ingest.sample_reads_per_tile > 0 {
+            num_clusters = cmp::min(num_clusters, settings.ingest.sample_reads_per_tile as usize);
+        }
+
+        Ok(StackReader {
+            cycles,
+            filter,
+            min_quality: settings.ingest.min_base_quality,
+            remaining: num_clusters,
+        })
+    }
+
+    /// Read the next chunk of clusters, decoding the cycles in parallel, `None` when done.
+    fn next_chunk(&mut self) -> Result<Option<Chunk>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let num_clusters = cmp::min(self.remaining, CHUNK_SIZE);
+        self.remaining -= num_clusters;
+
+        let min_quality = self.min_quality;
+        let codes = self
+            .cycles
+            .par_iter_mut()
+            .map(|cycle| {
+                (0..num_clusters)
+                    .map(|_| cycle.next_code(min_quality))
+                    .collect::<Result<Vec<u8>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let pf_flags = match self.filter {
+            Some(ref mut filter) => Some(
+                (0..num_clusters)
+                    .map(|_| filter.next_pf())
+                    .collect::<Result<Vec<bool>>>()?,
+            ),
+            None => None,
+        };
+
+        Ok(Some(Chunk { codes, pf_flags }))
+    }
+}
+
+/// Stream the clusters of `stack` chunk by chunk and count the index sequences.
+///
+/// Each chunk is counted in parallel with one map per worker before the next one is read.
+fn count_stack<K: SeqKey>(
+    logger: &slog::Logger,
+    stack: &TileBclStack,
+    cbcl_re: &Regex,
     settings: &Settings,
-) -> Result<Vec<char>> {
-    let table = vec!['A', 'C', 'G', 'T'];
-    let tile_no = tile_no as usize;
-    let mut result = Vec::new();
+) -> Result<TileCounts<K>> {
+    let mut reader = StackReader::open(logger, stack, cbcl_re, settings)?;
+    let pf_filtered = stack.non_pf_excluded || reader.filter.is_some();
 
-    let mut file = File::open(&path).chain_err(|| format!("Problem opening CBCL file {}", path))?;
-    let mut offset = header.header_size as usize;
-    for i in 0..tile_no {
-        offset += header.offset_infos[i].compressed_size as usize;
-    }
-    file.seek(SeekFrom::Start(offset as u64))
-        .chain_err(|| "Could not jump in CBCL file")?;
-    let mut gz_decoder = GzDecoder::new(file);
-    let num_bytes = cmp::min(
-        header.offset_infos[tile_no].num_clusters,
-        settings.ingest.sample_reads_per_tile as u32,
-    );
-    for j in 0..((num_bytes + 1) / 2) {
-        let b: u8 = gz_decoder
-            .read_u8()
-            .chain_err(|| "Problem reading data byte")?;
-        result.push(table[(b & 3) as usize]);
-        if num_bytes > j * 2 {
-            result.push(table[((b >> 4) & 3) as usize]);
+    // Build histogram, skipping non-PF reads and reads with too many no-calls.
+    let max_no_calls = settings.ingest.max_no_calls;
+    let mut hist = HashMap::new();
+    let mut num_counted = 0;
+    let mut num_non_pf = 0;
+    let mut num_skipped = 0;
+    while let Some(chunk) = reader.next_chunk()? {
+        let num_seqs = chunk.codes[0].len();
+        let (chunk_hist, chunk_non_pf, chunk_skipped) = (0..num_seqs)
+            .into_par_iter()
+            .fold(
+                || (HashMap::new(), 0, 0),
+                |(mut hist, mut num_non_pf, mut num_skipped), i| {
+                    let is_pf = match chunk.pf_flags {
+                        Some(ref pf_flags) => pf_flags[i],
+                        None => true,
+                    };
+                    if !is_pf {
+                        num_non_pf += 1;
+                    } else {
+                        let seq = K::pack(&chunk.codes, i);
+                        if max_no_calls >= 0 && seq.num_no_calls() > max_no_calls as u32 {
+                            num_skipped += 1;
+                        } else {
+                            *hist.entry(seq).or_insert(0) += 1;
+                        }
+                    }
+                    (hist, num_non_pf, num_skipped)
+                },
+            )
+            .reduce(
+                || (HashMap::new(), 0, 0),
+                |(hist_a, non_pf_a, skipped_a), (hist_b, non_pf_b, skipped_b)| {
+                    (
+                        merge_hists(hist_a, hist_b),
+                        non_pf_a + non_pf_b,
+                        skipped_a + skipped_b,
+                    )
+                },
+            );
+        num_counted += num_seqs - chunk_non_pf - chunk_skipped;
+        num_non_pf += chunk_non_pf;
+        num_skipped += chunk_skipped;
+        hist = merge_hists(hist, chunk_hist);
+    }
+    debug!(logger, "Left out {} non-PF reads", num_non_pf);
+
+    Ok(TileCounts {
+        num_counted,
+        num_skipped_no_calls: num_skipped,
+        pf_filtered,
+        hist,
+    })
+}
+
+/// Merge the smaller of the two histograms into the larger one.
+fn merge_hists<K: SeqKey>(a: HashMap<K, usize>, b: HashMap<K, usize>) -> HashMap<K, usize> {
+    let (mut into, from) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    for (seq, count) in from {
+        *into.entry(seq).or_insert(0) += count;
+    }
+    into
+}
+
+/// Count the stacks with the indices `selected` in `stacks_for_lane` with keys of type `K` and
+/// merge them into one `IndexCounts` before filtering.
+fn count_lane<K: SeqKey>(
+    logger: &slog::Logger,
+    stacks_for_lane: &[TileBclStack],
+    selected: &[usize],
+    cbcl_re: &Regex,
+    index_no: i32,
+    settings: &Settings,
+) -> Result<IndexCounts> {
+    let mut num_seqs = 0;
+    let mut num_skipped = 0;
+    let mut pf_filtered = true;
+    let mut hist = HashMap::new();
+    let tile_counts = selected
+        .par_iter()
+        .map(|stack_no| count_stack::<K>(logger, &stacks_for_lane[*stack_no], cbcl_re, settings))
+        .collect::<Result<Vec<_>>>()?;
+    for tile_counts in tile_counts {
+        num_seqs += tile_counts.num_counted;
+        num_skipped += tile_counts.num_skipped_no_calls;
+        pf_filtered = pf_filtered && tile_counts.pf_filtered;
+        hist = merge_hists(hist, tile_counts.hist);
+    }
+
+    // Filter to top 1% and only then convert to strings.
+    let seq_len = stacks_for_lane[0].paths.len();
+    let mut filtered_hist = HashMap::new();
+    for (seq, count) in hist {
+        if count as f64 > (num_seqs as f64) * settings.ingest.min_index_fraction {
+            filtered_hist.insert(seq.unpack(seq_len), count);
         }
     }
+    debug!(logger, "=> filtered hist {:?}", &filtered_hist);
+    info!(
+        logger,
+        "Lane {}: left out {} of {} reads with more than {} no-calls",
+        stacks_for_lane[0].lane_no,
+        num_skipped,
+        num_seqs + num_skipped,
+        settings.ingest.max_no_calls
+    );
 
-    Ok(result)
+    Ok(IndexCounts {
+        index_no,
+        lane_no: stacks_for_lane[0].lane_no,
+        sample_size: num_seqs,
+        num_skipped_no_calls: num_skipped,
+        pf_filtered,
+        hist: filtered_hist,
+    })
 }
 
-/// Analyze a single stack.
+/// Analyze the selected stacks of each lane.
+///
+/// `tile_selection[i]` gives the indices into `lane_stacks[i]` to read.  The tiles are counted in
+/// parallel, each into its own histogram, and the counts of all selected tiles of a lane are
+/// merged into one `IndexCounts` before filtering.  Index reads of up to `MAX_PACKED_LEN` cycles
+/// are counted as packed sequences, longer ones as strings.
 pub fn analyze_stacks(
     logger: &slog::Logger,
     lane_stacks: &Vec<Vec<TileBclStack>>,
-    stack_no: usize,
+    tile_selection: &Vec<Vec<usize>>,
     index_no: i32,
     settings: &Settings,
 ) -> Result<Vec<IndexCounts>> {
@@ -264,107 +754,118 @@ pub fn analyze_stacks(
 
     lane_stacks
         .par_iter()
-        .map(|ref stacks_for_lane| {
-            let stack = &stacks_for_lane[stack_no];
-            // Read in the bases from the bcl files.
-            let bases = stack
-                .paths
-                .par_iter()
-                .map(|ref path| {
-                    let chars = if cbcl_re.is_match(&path) {
-                        // Because we know that the RE matches, the following two unwraps cannot
-                        // fail.
-                        let captures = cbcl_re.captures(&path).unwrap();
-                        let cbcl_header = load_cbcl_header(logger, &captures[1])
-                            .chain_err(|| "Loading CBL header failed")?;
-                        load_from_cbcl(
-                            logger,
-                            &captures[1],
-                            &cbcl_header,
-                            captures[2].parse::<u32>().unwrap(),
-                            settings,
-                        )
-                        .chain_err(|| "Problem loading CBCL tile")?
-                    } else {
-                        let buf = if path.ends_with(".gz") || path.ends_with(".bgzf") {
-                            load_bcl_gz(logger, &path, settings)
-                        } else {
-                            load_bcl(logger, &path, settings)
-                        }
-                        .chain_err(|| "Problem loading BCL file.")?;
-
-                        // Build bases for each spot, use no-call if all bits are unset.
-                        let table = vec!['A', 'C', 'G', 'T'];
-                        let mut chars = Vec::new();
-                        for i in 0..buf.len() {
-                            if buf[i] == 0 {
-                                chars.push('N');
-                            } else {
-                                chars.push(table[(buf[i] & 3) as usize]);
-                            }
-                        }
-                        debug!(logger, "Done processing {}.", &path);
+        .zip(tile_selection.par_iter())
+        .map(|(stacks_for_lane, selected)| {
+            let seq_len = stacks_for_lane[0].paths.len();
+            if seq_len <= MAX_PACKED_LEN {
+                count_lane::<PackedSeq>(
+                    logger,
+                    stacks_for_lane,
+                    selected,
+                    &cbcl_re,
+                    index_no,
+                    settings,
+                )
+            } else {
+                debug!(
+                    logger,
+                    "Counting {} cycles as strings, more than {} cycles cannot be packed",
+                    seq_len,
+                    MAX_PACKED_LEN
+                );
+                count_lane::<String>(
+                    logger,
+                    stacks_for_lane,
+                    selected,
+                    &cbcl_re,
+                    index_no,
+                    settings,
+                )
+            }
+        })
+        .collect()
+}
 
-                        chars
-                    };
+/// Return `(surface, swath)` for an Illumina tile number such as `1101` or `21305`.
+///
+/// The first digit of the tile number is the surface and the second digit is the swath.
+pub fn tile_surface_swath(tile_no: i32) -> (i32, i32) {
+    let mut div = 1;
+    while tile_no / div >= 100 {
+        div *= 10;
+    }
+    let prefix = tile_no / div;
+    (prefix / 10, prefix % 10)
+}
 
-                    Ok(chars)
-                })
-                .collect::<Result<Vec<_>>>()?;
-
-            // Build read sequences.
-            debug!(logger, "Building read sequences.");
-            let num_seqs = bases[0].len();
-            let seqs = (0..num_seqs)
-                .into_par_iter()
-                .map(|i| {
-                    let mut seq = String::new();
-                    for j in 0..(bases.len()) {
-                        seq.push(bases[j][i]);
-                    }
-                    seq
-                })
-                .collect::<Vec<String>>();
-            debug!(logger, "Done building read sequences.");
+/// Select up to `num_tiles` of the stacks of one lane.
+///
+/// The stacks are grouped by surface and swath and the tiles are then taken round-robin from
+/// the shuffled groups such that the sample is spread over the whole lane.  If `num_tiles` is not
+/// positive then all stacks are selected.
+fn select_tiles<R: Rng>(stacks: &[TileBclStack], num_tiles: i32, rng: &mut R) -> Vec<usize> {
+    if num_tiles <= 0 || num_tiles as usize >= stacks.len() {
+        return (0..stacks.len()).collect();
+    }
 
-            // TODO: parallelize counting?
+    let mut groups: BTreeMap<(i32, i32), Vec<usize>> = BTreeMap::new();
+    for (i, stack) in stacks.iter().enumerate() {
+        groups
+            .entry(tile_surface_swath(stack.tile_no))
+            .or_default()
+            .push(i);
+    }
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    for group in groups.iter_mut() {
+        group.shuffle(rng);
+    }
+    groups.shuffle(rng);
 
-            // Build histogram.
-            let mut hist: HashMap<String, usize> = HashMap::new();
-            for seq in &seqs {
-                *hist.entry(seq.clone()).or_insert(1) += 1;
+    let mut result = Vec::new();
+    let mut round = 0;
+    while result.len() < num_tiles as usize {
+        for group in &groups {
+            if round < group.len() && result.len() < num_tiles as usize {
+                result.push(group[round]);
             }
+        }
+        round += 1;
+    }
+    result.sort();
+    result
+}
 
-            // Filter to top 1%.
-            let mut filtered_hist = HashMap::new();
-            for (seq, count) in hist {
-                if count as f64 > (num_seqs as f64) * settings.ingest.min_index_fraction {
-                    filtered_hist.insert(seq.clone(), count);
-                }
-            }
-            debug!(logger, "=> filtered hist {:?}", &filtered_hist);
+/// Return `dir/file_name` as a string if the file exists.
+fn existing_path(dir: &str, file_name: &str) -> Option<String> {
+    let path = Path::new(dir).join(file_name);
+    if path.exists() {
+        Some(path.to_str().unwrap().to_string())
+    } else {
+        None
+    }
+}
 
-            Ok(IndexCounts {
-                index_no: index_no,
-                lane_no: stack.lane_no,
-                sample_size: num_seqs,
-                hist: filtered_hist,
-            })
-        })
-        .collect()
+/// Extract the tile number from a file name such as `s_1_1101.bcl.gz`, `0` if there is none.
+pub fn tile_from_file_name(file_name: &str) -> i32 {
+    file_name
+        .split('.')
+        .next()
+        .and_then(|stem| stem.rsplit('_').next())
+        .and_then(|num| num.parse::<i32>().ok())
+        .unwrap_or(0)
 }
 
 /// Build tile-wise lists of files describing the BCL files for the given tile and each cycle.
 ///
-/// Note that for CBCL files, we generate file names such as `"path/to/file.cbcl!${tile_no}"`.
+/// Note that for CBCL files, we generate file names such as `"path/to/file.cbcl!${tile_no}"` where
+/// `tile_no` is the tile number from the CBCL header, e.g., `1101`.
 pub fn find_file_stacks(
-    _logger: &slog::Logger,
+    logger: &slog::Logger,
     folder_layout: FolderLayout,
     desc: &ReadDescription,
     path: &Path,
     start_cycle: i32,
 ) -> Result<Vec<Vec<TileBclStack>>> {
-    // TODO: currently we cannot sample more than one stack...
     match folder_layout {
         FolderLayout::MiniSeq => {
             let path = path
@@ -391,13 +892,23 @@ pub fn find_file_stacks(
                 }
                 lane_stacks.push(vec![TileBclStack {
                     lane_no: lane_no as i32 + 1,
+                    tile_no: 0,
                     paths: paths,
+                    filter_path: existing_path(lane_path, &format!("s_{}.filter", lane_no + 1)),
+                    non_pf_excluded: false,
                 }]);
             }
 
             Ok(lane_stacks)
         }
-        FolderLayout::MiSeq => {
+        FolderLayout::MiSeq | FolderLayout::HiSeqX => {
+            // MiSeq may write plain or gzip-compressed BCL files, HiSeq X/4000 always writes
+            // one `.bcl.gz` file per tile and cycle.
+            let suffixes: &[&str] = if folder_layout == FolderLayout::HiSeqX {
+                &[".gz"]
+            } else {
+                &["", ".gz"]
+            };
             let path = path
                 .join("Data")
                 .join("Intensities")
@@ -411,7 +922,7 @@ pub fn find_file_stacks(
             let mut tile_stacks = Vec::new();
             for (lane_no, ref lane_path) in lane_paths.iter().enumerate() {
                 let mut lane_stacks = Vec::new();
-                for suffix in &["", ".gz"] {
+                for suffix in suffixes {
                     let path = Path::new(lane_path)
                         .join("C1.1")
                         .join(format!("s_?_*.bcl{}", &suffix));
@@ -425,9 +936,16 @@ pub fn find_file_stacks(
                                 .join(file_name);
                             paths.push(path.to_str().unwrap().to_string());
                         }
+                        let file_name = file_name.to_str().unwrap();
                         lane_stacks.push(TileBclStack {
                             lane_no: lane_no as i32 + 1,
+                            tile_no: tile_from_file_name(file_name),
                             paths: paths,
+                            filter_path: existing_path(
+                                lane_path,
+                                &format!("{}.filter", file_name.split('.').next().unwrap()),
+                            ),
+                            non_pf_excluded: false,
                         });
                     }
                 }
@@ -436,66 +954,85 @@ pub fn find_file_stacks(
 
             Ok(tile_stacks)
         }
-        FolderLayout::NovaSeq => {
-            let path = path
-                .join("Data")
-                .join("Intensities")
-                .join("BaseCalls")
-                .join("L???");
-            let lane_paths = glob(path.to_str().unwrap())
-                .expect("Failed to read glob pattern")
-                .map(|x| x.unwrap().to_str().unwrap().to_string())
-                .collect::<Vec<String>>();
+        FolderLayout::NovaSeq | FolderLayout::NovaSeqX => {
+            find_cbcl_stacks(logger, desc, path, start_cycle)
+        }
+    }
+}
 
-            let mut tile_stacks = Vec::new();
-            for (lane_no, ref lane_path) in lane_paths.iter().enumerate() {
-                let mut lane_stacks = Vec::new();
-                let path = Path::new(lane_path)
-                    .join("C1.1")
-                    .join(format!("L???_?.cbcl"));
-                for prototype in glob(path.to_str().unwrap()).unwrap() {
-                    let path = prototype.unwrap();
-                    let file_name = path
-                        .file_name()
-                        .unwrap()
-                        .to_os_string()
-                        .into_string()
-                        .expect("Problem decoding string from OS")
-                        + "!0";
-                    let mut paths: Vec<String> = Vec::new();
-                    for cycle in start_cycle..(start_cycle + desc.num_cycles) {
-                        let path = Path::new(lane_path)
-                            .join(format!("C{}.1", cycle))
-                            .join(&file_name);
-                        paths.push(path.to_str().unwrap().to_string());
-                    }
-                    lane_stacks.push(TileBclStack {
-                        lane_no: lane_no as i32 + 1,
-                        paths: paths,
-                    });
+/// Build tile-wise lists of CBCL files (NovaSeq, NovaSeq X), one stack for each tile listed in
+/// the CBCL headers.
+fn find_cbcl_stacks(
+    logger: &slog::Logger,
+    desc: &ReadDescription,
+    path: &Path,
+    start_cycle: i32,
+) -> Result<Vec<Vec<TileBclStack>>> {
+    let path = path
+        .join("Data")
+        .join("Intensities")
+        .join("BaseCalls")
+        .join("L???");
+    let lane_paths = glob(path.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .map(|x| x.unwrap().to_str().unwrap().to_string())
+        .collect::<Vec<String>>();
+
+    let mut tile_stacks = Vec::new();
+    for (lane_no, ref lane_path) in lane_paths.iter().enumerate() {
+        let mut lane_stacks = Vec::new();
+        let path = Path::new(lane_path).join("C1.1").join("L???_?.cbcl");
+        for prototype in glob(path.to_str().unwrap()).unwrap() {
+            // Create one stack for each tile listed in the CBCL header, the tiles are the
+            // same for all cycles.
+            let path = prototype.unwrap();
+            let header = load_cbcl_header(logger, path.to_str().unwrap())
+                .chain_err(|| format!("Problem loading CBCL header of {:?}", &path))?;
+            let file_name = path
+                .file_name()
+                .unwrap()
+                .to_os_string()
+                .into_string()
+                .expect("Problem decoding string from OS");
+            for info in &header.offset_infos {
+                let mut paths: Vec<String> = Vec::new();
+                for cycle in start_cycle..(start_cycle + desc.num_cycles) {
+                    let path = Path::new(lane_path)
+                        .join(format!("C{}.1", cycle))
+                        .join(format!("{}!{}", &file_name, info.tile_no));
+                    paths.push(path.to_str().unwrap().to_string());
                 }
-                tile_stacks.push(lane_stacks);
+                lane_stacks.push(TileBclStack {
+                    lane_no: lane_no as i32 + 1,
+                    tile_no: info.tile_no as i32,
+                    paths,
+                    filter_path: existing_path(
+                        lane_path,
+                        &format!("s_{}_{}.filter", lane_no + 1, info.tile_no),
+                    ),
+                    non_pf_excluded: info.non_pf_flag,
+                });
             }
-
-            Ok(tile_stacks)
         }
-        _ => bail!(
-            "Don't know yet how to process folder layout {:?}",
-            folder_layout
-        ),
+        tile_stacks.push(lane_stacks);
     }
+
+    Ok(tile_stacks)
 }
 
 /// Sample adapters for the given index read described in `desc` and return
-/// `IndexCounts` for each lane.
+/// `IndexCounts` for each of the lanes with the numbers in `lanes`.
+#[allow(clippy::too_many_arguments)]
 pub fn sample_adapters(
     logger: &slog::Logger,
     path: &Path,
+    run_info: &RunInfo,
     desc: &ReadDescription,
     folder_layout: FolderLayout,
     settings: &Settings,
     index_no: i32,
     start_cycle: i32,
+    lanes: &[i32],
 ) -> Result<Vec<IndexCounts>> {
     // Depending on the directory layout, build stacks of files to get adapters from.
     // Through this abstraction, we can treat the different layouts the same in
@@ -504,12 +1041,239 @@ pub fn sample_adapters(
     let stacks = find_file_stacks(logger, folder_layout, desc, path, start_cycle)
         .chain_err(|| "Problem building paths to files")?;
 
+    sample_stacks(logger, path, run_info, stacks, settings, index_no, lanes)
+}
+
+/// Join the stacks of two index reads tile by tile, such that the clusters are read with the
+/// concatenated index sequences.
+///
+/// Tiles missing from `second` are left out with a warning.  It is an error if no tile of a lane
+/// is left.
+fn join_stacks(
+    logger: &slog::Logger,
+    first: Vec<Vec<TileBclStack>>,
+    second: Vec<Vec<TileBclStack>>,
+) -> Result<Vec<Vec<TileBclStack>>> {
+    let mut result = Vec::new();
+    for (first_for_lane, second_for_lane) in first.into_iter().zip(second) {
+        let lane_no = match first_for_lane.first() {
+            Some(stack) => stack.lane_no,
+            None => {
+                result.push(first_for_lane);
+                continue;
+            }
+        };
+        let mut joined = Vec::new();
+        let mut dropped = Vec::new();
+        for mut stack in first_for_lane {
+            match second_for_lane
+                .iter()
+                .find(|other| other.tile_no == stack.tile_no)
+            {
+                Some(other) => {
+                    stack.paths.extend(other.paths.iter().cloned());
+                    joined.push(stack);
+                }
+                None => dropped.push(stack.tile_no),
+            }
+        }
+        if !dropped.is_empty() {
+            warn!(
+                logger,
+                "Tiles {:?} of lane {} are missing from the second index read, leaving them out",
+                &dropped,
+                lane_no
+            );
+        }
+        if joined.is_empty() {
+            bail!(
+                "No tiles of lane {} found for both index reads, cannot analyze index pairs",
+                lane_no
+            );
+        }
+        result.push(joined);
+    }
+    Ok(result)
+}
+
+/// Sample the joint sequences of the two index reads described in `descs`, starting at the
+/// cycles in `start_cycles`, and return `IndexCounts` for each of the lanes with the numbers in
+/// `lanes`.
+///
+/// The histogram keys are the two index sequences joined by `"+"`, e.g., `"ACGTACGT+TTGGCCAA"`.
+#[allow(clippy::too_many_arguments)]
+pub fn sample_index_pairs(
+    logger: &slog::Logger,
+    path: &Path,
+    run_info: &RunInfo,
+    descs: (&ReadDescription, &ReadDescription),
+    start_cycles: (i32, i32),
+    folder_layout: FolderLayout,
+    settings: &Settings,
+    lanes: &[i32],
+) -> Result<Vec<IndexCounts>> {
+    info!(logger, "Getting paths to base call files of index pairs...");
+    let stacks = join_stacks(
+        logger,
+        find_file_stacks(logger, folder_layout, descs.0, path, start_cycles.0)
+            .chain_err(|| "Problem building paths to files")?,
+        find_file_stacks(logger, folder_layout, descs.1, path, start_cycles.1)
+            .chain_err(|| "Problem building paths to files")?,
+    )?;
+
+    let first_len = descs.0.num_cycles as usize;
+    let counts = sample_stacks(logger, path, run_info, stacks, settings, 0, lanes)?;
+    Ok(counts
+        .into_iter()
+        .map(|mut counts| {
+            counts.hist = counts
+                .hist
+                .into_iter()
+                .map(|(seq, count)| {
+                    (
+                        format!("{}+{}", &seq[..first_len], &seq[first_len..]),
+                        count,
+                    )
+                })
+                .collect();
+            counts
+        })
+        .collect())
+}
+
+/// Sample the stacks of the lanes with the numbers in `lanes` from `stacks` and return
+/// `IndexCounts` with `index_no` for each of them.
+fn sample_stacks(
+    logger: &slog::Logger,
+    path: &Path,
+    run_info: &RunInfo,
+    stacks: Vec<Vec<TileBclStack>>,
+    settings: &Settings,
+    index_no: i32,
+    lanes: &[i32],
+) -> Result<Vec<IndexCounts>> {
+    // Only sample from the selected lanes and from tiles that are listed in `RunInfo.xml`, if
+    // the tiles are listed there.
+    let stacks = stacks
+        .into_iter()
+        .filter(|stacks_for_lane| match stacks_for_lane.first() {
+            Some(stack) => lanes.contains(&stack.lane_no),
+            None => true,
+        })
+        .map(|stacks_for_lane| {
+            stacks_for_lane
+                .into_iter()
+                .filter(|stack| {
+                    stack.tile_no == 0
+                        || run_info.tiles.is_empty()
+                        || run_info
+                            .tiles
+                            .contains(&format!("{}_{}", stack.lane_no, stack.tile_no))
+                })
+                .collect::<Vec<TileBclStack>>()
+        })
+        .collect::<Vec<Vec<TileBclStack>>>();
+    if stacks.is_empty()
+        || stacks
+            .iter()
+            .any(|stacks_for_lane| stacks_for_lane.is_empty())
+    {
+        bail!("Found no base call files to sample from in {:?}", path);
+    }
+
     let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(settings.seed);
-    let stack_no = rng.gen_range(0, stacks[0].len());
+    let tile_selection = stacks
+        .iter()
+        .map(|stacks_for_lane| {
+            let selected = select_tiles(stacks_for_lane, settings.ingest.sample_tiles, &mut rng);
+            debug!(
+                logger,
+                "Sampling tiles {:?}",
+                selected
+                    .iter()
+                    .map(|i| stacks_for_lane[*i].tile_no)
+                    .collect::<Vec<i32>>()
+            );
+            selected
+        })
+        .collect::<Vec<Vec<usize>>>();
 
     info!(logger, "Analyzing base call files...");
-    let counts = analyze_stacks(logger, &stacks, stack_no, index_no, settings)
+    let counts = analyze_stacks(logger, &stacks, &tile_selection, index_no, settings)
         .chain_err(|| "Problem with analyzing stacks")?;
 
     Ok(counts)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(tile_no: i32) -> TileBclStack {
+        TileBclStack {
+            lane_no: 1,
+            tile_no,
+            paths: vec![format!("{}.bcl", tile_no)],
+            filter_path: None,
+            non_pf_excluded: false,
+        }
+    }
+
+    #[test]
+    fn tile_surface_swath_four_digits() {
+        assert_eq!(tile_surface_swath(1101), (1, 1));
+        assert_eq!(tile_surface_swath(2114), (2, 1));
+    }
+
+    #[test]
+    fn tile_surface_swath_five_digits() {
+        assert_eq!(tile_surface_swath(21305), (2, 1));
+        assert_eq!(tile_surface_swath(12478), (1, 2));
+    }
+
+    #[test]
+    fn select_tiles_all() {
+        let stacks = vec![stack(1101), stack(1102), stack(2101)];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(42);
+        assert_eq!(select_tiles(&stacks, 0, &mut rng), vec![0, 1, 2]);
+        assert_eq!(select_tiles(&stacks, -1, &mut rng), vec![0, 1, 2]);
+        assert_eq!(select_tiles(&stacks, 3, &mut rng), vec![0, 1, 2]);
+        assert_eq!(select_tiles(&stacks, 10, &mut rng), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn select_tiles_spread() {
+        let stacks = vec![
+            stack(1101),
+            stack(1102),
+            stack(1201),
+            stack(2101),
+            stack(2102),
+        ];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(42);
+        let selected = select_tiles(&stacks, 3, &mut rng);
+        assert_eq!(selected.len(), 3);
+        let groups = selected
+            .iter()
+            .map(|i| tile_surface_swath(stacks[*i].tile_no))
+            .collect::<std::collections::BTreeSet<(i32, i32)>>();
+        assert_eq!(groups.len(), 3);
+    }
+
+    #[test]
+    fn join_stacks_by_tile() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let joined = join_stacks(
+            &logger,
+            vec![vec![stack(1101), stack(1102)]],
+            vec![vec![stack(1102)]],
+        )
+        .unwrap();
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined[0].len(), 1);
+        assert_eq!(joined[0][0].tile_no, 1102);
+        assert_eq!(joined[0][0].paths, vec!["1102.bcl", "1102.bcl"]);
+
+        assert!(join_stacks(&logger, vec![vec![stack(1101)]], vec![vec![stack(1102)]]).is_err());
+    }
+}
@@ -1,23 +1,51 @@
 //! Code for accessing data in the raw output directories.
 
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use glob::glob;
+use std::cmp;
+use std::fs;
 use std::path::Path;
-use sxd_document::dom::Document;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use sxd_document::dom::{Document, Element};
+use sxd_document::parser;
 use sxd_xpath::nodeset::Node;
 use sxd_xpath::{evaluate_xpath, Value};
 
 use super::super::errors::*;
+use super::interop::load_highest_extracted_cycle;
+use super::status::*;
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
 pub enum FolderLayout {
     /// MiSeq, HiSeq 2000, etc. `runParameters.xml`
     MiSeq,
     /// MiniSeq, NextSeq etc. `RunParameters.xml`
     MiniSeq,
-    /// HiSeq X
+    /// HiSeq X, HiSeq 3000/4000 (patterned flow cells), `RunParameters.xml`
     HiSeqX,
     /// NovaSeq
     NovaSeq,
+    /// NovaSeq X (RTA4), `RunParameters.xml` with `PlannedReads`
+    NovaSeqX,
+}
+
+impl FolderLayout {
+    /// Return the name of the run parameters XML file.
+    pub fn run_parameters_file(self) -> &'static str {
+        match self {
+            FolderLayout::MiSeq => "runParameters.xml",
+            FolderLayout::MiniSeq
+            | FolderLayout::HiSeqX
+            | FolderLayout::NovaSeq
+            | FolderLayout::NovaSeqX => "RunParameters.xml",
+        }
+    }
+
+    /// Return whether the instrument writes `CopyComplete.txt` once all output was copied.
+    pub fn writes_copy_complete(self) -> bool {
+        matches!(self, FolderLayout::NovaSeq | FolderLayout::NovaSeqX)
+    }
 }
 
 pub fn guess_folder_layout(path: &Path) -> Result<FolderLayout> {
@@ -38,6 +66,11 @@ pub fn guess_folder_layout(path: &Path) -> Result<FolderLayout> {
     ];
     let hiseqx_marker = vec![
         path.join("Data").join("Intensities").join("s.locs"),
+        path.join("Data")
+            .join("Intensities")
+            .join("BaseCalls")
+            .join("L001")
+            .join("C1.1"),
         path.join("RunParameters.xml"),
     ];
     let novaseq_marker_any = vec![
@@ -55,23 +88,31 @@ pub fn guess_folder_layout(path: &Path) -> Result<FolderLayout> {
             .join("L001_2.cbcl"),
     ];
     let novaseq_marker_all = vec![path.join("RunParameters.xml")];
+    // Only NovaSeq X names itself in the parameters; `IsReverseComplement` read attributes in
+    // `RunInfo.xml` are also written by NovaSeq 6000 (v1.5 reagents) and NextSeq 2000.
+    let is_novaseqx = fs::read_to_string(path.join("RunParameters.xml"))
+        .map(|s| s.contains("<InstrumentType>NovaSeqX"))
+        .unwrap_or(false);
 
-    if novaseq_marker_all.iter().all(|ref m| m.exists())
+    if is_novaseqx {
+        Ok(FolderLayout::NovaSeqX)
+    } else if novaseq_marker_all.iter().all(|ref m| m.exists())
         && novaseq_marker_any.iter().any(|ref m| m.exists())
     {
         Ok(FolderLayout::NovaSeq)
     } else if miseq_marker.iter().all(|ref m| m.exists()) {
         Ok(FolderLayout::MiSeq)
-    } else if miniseq_markers.iter().all(|ref m| m.exists()) {
-        Ok(FolderLayout::MiniSeq)
     } else if hiseqx_marker.iter().all(|ref m| m.exists()) {
+        // Must come before MiniSeq as HiSeq X folders also have `L001` and `RunParameters.xml`.
         Ok(FolderLayout::HiSeqX)
+    } else if miniseq_markers.iter().all(|ref m| m.exists()) {
+        Ok(FolderLayout::MiniSeq)
     } else {
         bail!("Could not guess folder layout from {:?}", path)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct ReadDescription {
     pub number: i32,
     pub num_cycles: i32,
@@ -86,7 +127,7 @@ pub fn string_description(read_descs: &Vec<ReadDescription>) -> String {
         .join("")
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RunInfo {
     /// The long, full run ID.
     pub run_id: String,
@@ -96,151 +137,248 @@ pub struct RunInfo {
     pub date: String,
     pub lane_count: i32,
     pub reads: Vec<ReadDescription>,
+    /// The tiles from the `TileSet`, e.g., `"1_1101"` for lane 1, tile 1101.  Empty for older
+    /// instruments that do not list the tiles.
+    pub tiles: Vec<String>,
 }
 
-pub fn process_xml_run_info(info_doc: &Document) -> Result<RunInfo> {
-    let reads = if let Value::Nodeset(nodeset) =
-        evaluate_xpath(&info_doc, "//RunInfoRead|//Read")
-            .chain_err(|| "Problem finding Read or RunInfoRead tags")?
+/// Return `ErrorKind::Metadata` error for a problem with `xpath` in `file`.
+fn metadata_error(file: &str, xpath: &str, message: &str) -> Error {
+    ErrorKind::Metadata(file.to_string(), xpath.to_string(), message.to_string()).into()
+}
+
+/// Return value of attribute `name` of element `elem` found at `xpath` in `file`, parsed as `T`.
+fn parse_attribute<T: FromStr>(elem: &Element, name: &str, file: &str, xpath: &str) -> Result<T> {
+    let attr_xpath = format!("{}/@{}", xpath, name);
+    let value = elem
+        .attribute(name)
+        .ok_or_else(|| metadata_error(file, &attr_xpath, "attribute is missing"))?
+        .value();
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| metadata_error(file, &attr_xpath, &format!("invalid value {:?}", value)))
+}
+
+/// Return the non-empty text at `xpath` in `doc` read from `file`.
+fn required_string(doc: &Document, xpath: &str, file: &str) -> Result<String> {
+    let value = evaluate_xpath(doc, xpath)
+        .map_err(|e| metadata_error(file, xpath, &format!("{:?}", e)))?
+        .into_string();
+    if value.trim().is_empty() {
+        Err(metadata_error(file, xpath, "value is missing"))
+    } else {
+        Ok(value.trim().to_string())
+    }
+}
+
+/// Return the integer at `xpath` in `doc` read from `file`.
+fn required_number(doc: &Document, xpath: &str, file: &str) -> Result<i32> {
+    let value = required_string(doc, xpath, file)?;
+    value
+        .parse::<i32>()
+        .map_err(|_| metadata_error(file, xpath, &format!("invalid number {:?}", value)))
+}
+
+/// Parse the `Read`/`RunInfoRead` elements from the given document read from `file`.
+///
+/// Reads with zero cycles are ignored.
+fn process_xml_reads(doc: &Document, file: &str) -> Result<Vec<ReadDescription>> {
+    let xpath = "//RunInfoRead|//Read";
+    if let Value::Nodeset(nodeset) =
+        evaluate_xpath(doc, xpath).chain_err(|| "Problem finding Read or RunInfoRead tags")?
     {
         let mut reads = Vec::new();
         for node in nodeset.document_order() {
             if let Node::Element(elem) = node {
-                let num_cycles = elem
-                    .attribute("NumCycles")
-                    .expect("Problem accessing NumCycles attribute")
-                    .value()
-                    .to_string()
-                    .parse::<i32>()
-                    .unwrap();
+                let xpath = format!("//{}", elem.name().local_part());
+                let num_cycles = parse_attribute::<i32>(&elem, "NumCycles", file, &xpath)?;
                 if num_cycles > 0 {
                     reads.push(ReadDescription {
-                        number: elem
-                            .attribute("Number")
-                            .expect("Problem accessing Number attribute")
-                            .value()
-                            .to_string()
-                            .parse::<i32>()
-                            .unwrap(),
-                        num_cycles: num_cycles,
-                        is_index: elem
-                            .attribute("IsIndexedRead")
-                            .expect("Problem accessing IsIndexedRead attribute")
-                            .value()
+                        number: parse_attribute(&elem, "Number", file, &xpath)?,
+                        num_cycles,
+                        is_index: parse_attribute::<String>(&elem, "IsIndexedRead", file, &xpath)?
                             == "Y",
                     })
                 }
             } else {
-                bail!("Read was not a tag!")
+                bail!("Read or RunInfoRead was not a tag!")
             }
         }
-        reads
+        Ok(reads)
     } else {
         bail!("Problem getting Read or RunInfoRead elements")
-    };
+    }
+}
 
-    let xml_date = evaluate_xpath(&info_doc, "//Date/text()")
-        .chain_err(|| "Problem reading //Date/text()")?
-        .into_string();
+pub fn process_xml_run_info(info_doc: &Document) -> Result<RunInfo> {
+    let file = "RunInfo.xml";
+    let reads = process_xml_reads(info_doc, file)?;
+
+    let xml_date = required_string(info_doc, "//Date/text()", file)?;
     let date_string = if let Ok(good) = NaiveDate::parse_from_str(&xml_date, "%y%m%d") {
         good.format("%F").to_string()
     } else {
         if let Ok(good) = NaiveDateTime::parse_from_str(&xml_date, "%-m/%-d/%Y %-I:%M:%S %p") {
             good.format("%F").to_string()
+        } else if let Ok(good) = DateTime::parse_from_rfc3339(&xml_date) {
+            // ISO 8601 as written by NovaSeq X, e.g., `2023-05-19T14:32:09Z`
+            good.format("%F").to_string()
+        } else if let Ok(good) = NaiveDateTime::parse_from_str(&xml_date, "%Y-%m-%dT%H:%M:%S") {
+            good.format("%F").to_string()
         } else {
-            bail!("Could not parse date from string {}", &xml_date);
+            return Err(metadata_error(
+                file,
+                "//Date/text()",
+                &format!("could not parse date {:?}", &xml_date),
+            ));
         }
     };
 
+    let tiles = if let Value::Nodeset(nodeset) =
+        evaluate_xpath(info_doc, "//FlowcellLayout/TileSet/Tiles/Tile")
+            .chain_err(|| "Problem reading //FlowcellLayout/TileSet/Tiles/Tile")?
+    {
+        nodeset
+            .document_order()
+            .iter()
+            .map(|node| node.string_value().trim().to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
     Ok(RunInfo {
-        run_id: evaluate_xpath(&info_doc, "//Run/@Id")
+        run_id: evaluate_xpath(info_doc, "//Run/@Id")
             .chain_err(|| "Problem reading //Run/@Id")?
             .into_string(),
-        run_number: evaluate_xpath(&info_doc, "//Run/@Number")
-            .chain_err(|| "Problem reading //Run/@Number")?
-            .into_number() as i32,
-        flowcell: evaluate_xpath(&info_doc, "//Flowcell/text()")
-            .chain_err(|| "Problem reading //Flowcell/text()")?
-            .into_string(),
-        instrument: evaluate_xpath(&info_doc, "//Instrument/text()")
-            .chain_err(|| "Problem reading //Instrument/text()")?
-            .into_string(),
+        run_number: required_number(info_doc, "//Run/@Number", file)?,
+        flowcell: required_string(info_doc, "//Flowcell/text()", file)?,
+        instrument: required_string(info_doc, "//Instrument/text()", file)?,
         date: date_string,
-        lane_count: evaluate_xpath(&info_doc, "//FlowcellLayout/@LaneCount")
-            .chain_err(|| "Problem reading //FlowcellLayout/@LaneCount")?
-            .into_number() as i32,
+        lane_count: required_number(info_doc, "//FlowcellLayout/@LaneCount", file)?,
         reads: reads,
+        tiles,
     })
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RunParameters {
     pub planned_reads: Vec<ReadDescription>,
     pub rta_version: String,
+    /// The XPath that `rta_version` was read from, for reporting problems.
+    #[serde(skip)]
+    pub rta_version_xpath: &'static str,
     pub run_number: i32,
     pub flowcell_slot: String,
     pub experiment_name: String,
 }
 
-pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters> {
-    let reads = if let Value::Nodeset(nodeset) =
-        evaluate_xpath(&info_doc, "//RunInfoRead|//Read")
-            .chain_err(|| "Problem finding Read or RunInfoRead tags")?
-    {
-        let mut reads = Vec::new();
-        for node in nodeset.document_order() {
-            if let Node::Element(elem) = node {
-                let num_cycles = elem
-                    .attribute("NumCycles")
-                    .expect("Problem accessing NumCycles attribute")
-                    .value()
-                    .to_string()
-                    .parse::<i32>()
-                    .unwrap();
-                if num_cycles > 0 {
-                    reads.push(ReadDescription {
-                        number: elem
-                            .attribute("Number")
-                            .expect("Problem accessing Number attribute")
-                            .value()
-                            .to_string()
-                            .parse::<i32>()
-                            .unwrap(),
-                        num_cycles: num_cycles,
-                        is_index: elem
-                            .attribute("IsIndexedRead")
-                            .expect("Problem accessing IsIndexedRead attribute")
-                            .value()
-                            == "Y",
-                    })
-                }
-            } else {
-                bail!("Read or RunInfoRead was not a tag!")
-            }
-        }
-        reads
-    } else {
-        bail!("Problem getting Read or RunInfoRead elements")
+/// Return the planned reads given by the elements at `xpaths` in `doc` read from `file`, each
+/// containing the number of cycles and given with whether it is an index read.
+///
+/// Missing elements and reads with zero cycles are ignored, e.g., the second index read of
+/// single-index runs.  Elements that are present must contain a number.
+fn process_xml_read_cycles(
+    doc: &Document,
+    xpaths: &[(&str, bool)],
+    file: &str,
+) -> Result<Vec<ReadDescription>> {
+    let exists = |xpath: &str| {
+        evaluate_xpath(doc, &format!("boolean({})", xpath))
+            .map(|value| value.into_boolean())
+            .unwrap_or(false)
     };
 
-    let rta_version = evaluate_xpath(&info_doc, "//RTAVersion/text()")
-        .chain_err(|| "Problem getting RTAVersion element")?
-        .into_string();
-    let rta_version3 = evaluate_xpath(&info_doc, "//RtaVersion/text()")
-        .chain_err(|| "Problem getting RTAVersion element")?
+    let mut reads = Vec::new();
+    for (xpath, is_index) in xpaths {
+        if !exists(xpath) {
+            continue;
+        }
+        let num_cycles = required_number(doc, &format!("{}/text()", xpath), file)?;
+        if num_cycles > 0 {
+            reads.push(ReadDescription {
+                number: reads.len() as i32 + 1,
+                num_cycles,
+                is_index: *is_index,
+            });
+        }
+    }
+    Ok(reads)
+}
+
+/// Return the RTA version from `doc` read from `file` and the XPath it was read from, preferring
+/// the RTA 3 `RtaVersion` element without the leading `v`.
+fn process_xml_rta_version(doc: &Document, file: &str) -> Result<(String, &'static str)> {
+    let rta3_xpath = "//RtaVersion/text()";
+    let rta_version3 = evaluate_xpath(doc, rta3_xpath)
+        .map_err(|e| metadata_error(file, rta3_xpath, &format!("{:?}", e)))?
         .into_string();
+    if !rta_version3.trim().is_empty() {
+        Ok((
+            rta_version3.trim().trim_start_matches('v').to_string(),
+            rta3_xpath,
+        ))
+    } else {
+        let xpath = "//RTAVersion/text()";
+        Ok((required_string(doc, xpath, file)?, xpath))
+    }
+}
+
+pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters> {
+    let file = FolderLayout::MiSeq.run_parameters_file();
+    let reads = process_xml_reads(info_doc, file)?;
+    let (rta_version, rta_version_xpath) = process_xml_rta_version(info_doc, file)?;
 
     Ok(RunParameters {
         planned_reads: reads,
-        rta_version: if !rta_version3.is_empty() {
-            rta_version3[1..].to_string()
+        rta_version,
+        rta_version_xpath,
+        run_number: required_number(info_doc, "//ScanNumber/text()", file)?,
+        flowcell_slot: if let Ok(elem) = evaluate_xpath(info_doc, "//FCPosition/text()") {
+            let elem = elem.into_string();
+            if elem.is_empty() {
+                "A".to_string()
+            } else {
+                elem
+            }
         } else {
-            rta_version
+            "A".to_string()
+        },
+        experiment_name: if let Ok(elem) = evaluate_xpath(info_doc, "//ExperimentName/text()") {
+            elem.into_string()
+        } else {
+            "".to_string()
         },
-        run_number: evaluate_xpath(&info_doc, "//ScanNumber/text()")
-            .chain_err(|| "Problem getting ScanNumber element")?
-            .into_number() as i32,
-        flowcell_slot: if let Ok(elem) = evaluate_xpath(&info_doc, "//FCPosition/text()") {
+    })
+}
+
+pub fn process_xml_param_doc_hiseqx(info_doc: &Document) -> Result<RunParameters> {
+    let file = FolderLayout::HiSeqX.run_parameters_file();
+    // HiSeq X/3000/4000 list the reads as `RunInfoRead` elements below `Setup/Reads` but some
+    // control software versions only write the `Read1`, `IndexRead1`, ... elements.
+    let mut reads = process_xml_reads(info_doc, file)?;
+    if reads.is_empty() {
+        reads = process_xml_read_cycles(
+            info_doc,
+            &[
+                ("//Setup/Read1", false),
+                ("//Setup/IndexRead1", true),
+                ("//Setup/IndexRead2", true),
+                ("//Setup/Read2", false),
+            ],
+            file,
+        )?;
+    }
+
+    let (rta_version, rta_version_xpath) = process_xml_rta_version(info_doc, file)?;
+
+    Ok(RunParameters {
+        planned_reads: reads,
+        rta_version,
+        rta_version_xpath,
+        run_number: required_number(info_doc, "//ScanNumber/text()", file)?,
+        flowcell_slot: if let Ok(elem) = evaluate_xpath(info_doc, "//FCPosition/text()") {
             let elem = elem.into_string();
             if elem.is_empty() {
                 "A".to_string()
@@ -250,7 +388,7 @@ pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters>
         } else {
             "A".to_string()
         },
-        experiment_name: if let Ok(elem) = evaluate_xpath(&info_doc, "//ExperimentName/text()") {
+        experiment_name: if let Ok(elem) = evaluate_xpath(info_doc, "//ExperimentName/text()") {
             elem.into_string()
         } else {
             "".to_string()
@@ -259,76 +397,87 @@ pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters>
 }
 
 pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameters> {
-    let mut reads = Vec::new();
-    let mut number = 1;
+    let file = FolderLayout::MiniSeq.run_parameters_file();
+    let reads = process_xml_read_cycles(
+        info_doc,
+        &[
+            ("//PlannedRead1Cycles", false),
+            ("//PlannedIndex1ReadCycles", true),
+            ("//PlannedIndex2ReadCycles", true),
+            ("//PlannedRead2Cycles", false),
+        ],
+        file,
+    )?;
+    let (rta_version, rta_version_xpath) = process_xml_rta_version(info_doc, file)?;
 
-    if let Ok(value) = evaluate_xpath(&info_doc, "//PlannedRead1Cycles/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: false,
-            });
-            number += 1;
-        }
-    }
-
-    if let Ok(value) = evaluate_xpath(&info_doc, "//PlannedIndex1ReadCycles/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: true,
-            });
-            number += 1;
-        }
-    }
-
-    if let Ok(value) = evaluate_xpath(&info_doc, "//PlannedIndex2ReadCycles/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: true,
-            });
-            number += 1;
-        }
-    }
+    Ok(RunParameters {
+        planned_reads: reads,
+        rta_version,
+        rta_version_xpath,
+        run_number: required_number(info_doc, "//RunNumber/text()", file)?,
+        flowcell_slot: "A".to_string(), // always Slot A
+        experiment_name: if let Ok(elem) = evaluate_xpath(info_doc, "//ExperimentName/text()") {
+            elem.into_string()
+        } else {
+            "".to_string()
+        },
+    })
+}
 
-    if let Ok(value) = evaluate_xpath(&info_doc, "//PlannedRead2Cycles/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: false,
-            });
-            // number += 1;
+pub fn process_xml_param_doc_novaseqx(info_doc: &Document) -> Result<RunParameters> {
+    let file = FolderLayout::NovaSeqX.run_parameters_file();
+    // The planned reads are given as `<Read ReadName="Index1" Cycles="10"/>` in `PlannedReads`.
+    let xpath = "//PlannedReads/Read";
+    let mut reads = Vec::new();
+    if let Value::Nodeset(nodeset) =
+        evaluate_xpath(info_doc, xpath).chain_err(|| "Problem finding PlannedReads/Read tags")?
+    {
+        for node in nodeset.document_order() {
+            if let Node::Element(elem) = node {
+                let num_cycles = parse_attribute::<i32>(&elem, "Cycles", file, xpath)?;
+                if num_cycles > 0 {
+                    reads.push(ReadDescription {
+                        number: reads.len() as i32 + 1,
+                        num_cycles,
+                        is_index: elem
+                            .attribute("ReadName")
+                            .map(|attr| attr.value().starts_with("Index"))
+                            .unwrap_or(false),
+                    });
+                }
+            } else {
+                bail!("PlannedReads/Read was not a tag!")
+            }
         }
     }
 
-    let rta_version = evaluate_xpath(&info_doc, "//RTAVersion/text()")
-        .chain_err(|| "Problem getting RTAVersion element")?
-        .into_string();
-    let rta_version3 = evaluate_xpath(&info_doc, "//RtaVersion/text()")
-        .chain_err(|| "Problem getting RTAVersion element")?
+    let rta_version_xpath = "//RtaVersion/text()|//RTAVersion/text()";
+    let rta_version = evaluate_xpath(info_doc, rta_version_xpath)
+        .chain_err(|| "Problem getting RtaVersion element")?
         .into_string();
+    let rta_version = rta_version.trim_start_matches('v').to_string();
 
     Ok(RunParameters {
         planned_reads: reads,
-        rta_version: if !rta_version3.is_empty() {
-            rta_version3[1..].to_string()
+        rta_version: if rta_version.is_empty() {
+            // NovaSeq X runs RTA4 on board but does not always write the version.
+            "4".to_string()
         } else {
             rta_version
         },
-        run_number: evaluate_xpath(&info_doc, "//RunNumber/text()")
-            .chain_err(|| "Problem getting RunNumber element")?
-            .into_number() as i32,
-        flowcell_slot: "A".to_string(), // always Slot A
-        experiment_name: if let Ok(elem) = evaluate_xpath(&info_doc, "//ExperimentName/text()") {
+        rta_version_xpath,
+        run_number: required_number(info_doc, "//RunCounter/text()", file)?,
+        flowcell_slot: if let Ok(elem) = evaluate_xpath(info_doc, "//Side/text()") {
+            let elem = elem.into_string();
+            if elem.is_empty() {
+                "A".to_string()
+            } else {
+                elem
+            }
+        } else {
+            "A".to_string()
+        },
+        experiment_name: if let Ok(elem) = evaluate_xpath(info_doc, "//ExperimentName/text()") {
             elem.into_string()
         } else {
             "".to_string()
@@ -347,32 +496,220 @@ pub fn process_xml(
 
     let run_params = match folder_layout {
         FolderLayout::MiSeq => process_xml_param_doc_miseq(param_doc)?,
+        FolderLayout::HiSeqX => process_xml_param_doc_hiseqx(param_doc)?,
         FolderLayout::MiniSeq | FolderLayout::NovaSeq => process_xml_param_doc_miniseq(param_doc)?,
-        _ => bail!(
-            "Don't yet know how to parse folder layout {:?}",
-            folder_layout
-        ),
+        FolderLayout::NovaSeqX => process_xml_param_doc_novaseqx(param_doc)?,
     };
     debug!(logger, "RunParameters => {:?}", &run_params);
 
     Ok((run_info, run_params))
 }
 
+/// Return the major RTA version from `run_params` read for `folder_layout`.
+pub fn rta_major_version(run_params: &RunParameters, folder_layout: FolderLayout) -> Result<i32> {
+    let major = run_params.rta_version.split('.').next().unwrap_or("");
+    major.trim().parse::<i32>().map_err(|_| {
+        metadata_error(
+            folder_layout.run_parameters_file(),
+            run_params.rta_version_xpath,
+            &format!("invalid RTA version {:?}", run_params.rta_version),
+        )
+    })
+}
+
+/// Return whether the performed reads from `run_info` differ from the planned ones in
+/// `run_params`.
+pub fn reads_mismatch(run_info: &RunInfo, run_params: &RunParameters) -> bool {
+    !run_params.planned_reads.is_empty() && run_info.reads != run_params.planned_reads
+}
+
+/// Completion status written by the instrument to `RunCompletionStatus.xml`.
+#[derive(Debug)]
+pub struct RunCompletionStatus {
+    /// The status, e.g., `CompletedAsPlanned` or `RunErrored`.
+    pub status: String,
+    /// Description of the error for aborted runs.
+    pub error_description: Option<String>,
+}
+
+impl RunCompletionStatus {
+    /// Return whether the run completed as planned.
+    pub fn is_success(&self) -> bool {
+        self.status == "CompletedAsPlanned"
+    }
+}
+
+/// Read `RunCompletionStatus.xml` from the flow cell directory at `path`, `None` if missing.
+///
+/// NovaSeq 6000 writes the status to `RunStatus`, NovaSeq X to `CompletionStatus`.
+pub fn read_run_completion_status(path: &Path) -> Result<Option<RunCompletionStatus>> {
+    let xml_path = path.join("RunCompletionStatus.xml");
+    if !xml_path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(&xml_path).chain_err(|| "Problem reading RunCompletionStatus.xml")?;
+    let pkg = parser::parse(&contents).chain_err(|| "Problem parsing RunCompletionStatus.xml")?;
+    let doc = pkg.as_document();
+
+    let status = evaluate_xpath(&doc, "//RunStatus/text()|//CompletionStatus/text()")
+        .chain_err(|| "Problem reading run status from RunCompletionStatus.xml")?
+        .into_string();
+    if status.trim().is_empty() {
+        bail!("RunCompletionStatus.xml does not contain a run status");
+    }
+    let error_description = evaluate_xpath(&doc, "//ErrorDescription/text()")
+        .chain_err(|| "Problem reading error description from RunCompletionStatus.xml")?
+        .into_string();
+    let error_description = error_description.trim();
+
+    Ok(Some(RunCompletionStatus {
+        status: status.trim().to_string(),
+        error_description: if error_description.is_empty() || error_description == "None" {
+            None
+        } else {
+            Some(error_description.to_string())
+        },
+    }))
+}
+
+/// Return the reason why the run in `path` failed according to the instrument or because of
+/// differing read structures, `None` if there is no such failure.
+pub fn get_failure_reason(
+    run_info: &RunInfo,
+    run_params: &RunParameters,
+    path: &Path,
+) -> Result<Option<String>> {
+    if let Some(completion) = read_run_completion_status(path)? {
+        if !completion.is_success() {
+            return Ok(Some(format!(
+                "The instrument reported the run status \"{}\" in RunCompletionStatus.xml: {}",
+                completion.status,
+                completion
+                    .error_description
+                    .unwrap_or_else(|| "no error description given".to_string())
+            )));
+        }
+    }
+    if reads_mismatch(run_info, run_params) {
+        return Ok(Some(
+            "The indication of performed and planned cycles differ.".to_string(),
+        ));
+    }
+    Ok(None)
+}
+
+/// Return the sequencing status after updating `current_status` with the one detected from
+/// `run_info`, `run_params`, and the marker files in `path`.
+///
+/// Runs are complete when `RTAComplete.txt` exists and, for instruments writing it,
+/// `CopyComplete.txt` as well.  Runs fail when the instrument reports so in
+/// `RunCompletionStatus.xml` or the read structures differ.  Unfinished runs are considered
+/// failed if `stalled` is set.
 pub fn get_status_sequencing(
     run_info: &RunInfo,
     run_params: &RunParameters,
     path: &Path,
-    current_status: &str,
-) -> String {
-    if current_status == "closed" || current_status == "complete" {
-        // has final status
-        return current_status.to_string();
-    } else if (!run_params.planned_reads.is_empty()) && (run_info.reads != run_params.planned_reads)
-    {
-        return "failed".to_string();
+    folder_layout: FolderLayout,
+    current_status: Status,
+    stalled: bool,
+) -> Result<Status> {
+    let copied = !folder_layout.writes_copy_complete() || path.join("CopyComplete.txt").exists();
+    let detected = if get_failure_reason(run_info, run_params, path)?.is_some() {
+        Status::Failed
     } else if path.join("RTAComplete.txt").exists() {
-        return "complete".to_string();
+        if copied {
+            Status::Complete
+        } else {
+            Status::InProgress
+        }
+    } else if stalled {
+        Status::Failed
     } else {
-        return "in_progress".to_string();
+        Status::InProgress
+    };
+    Ok(transition(StatusKind::Sequencing, current_status, detected))
+}
+
+/// Return the point of time of the last write to the flow cell directory at `path`.
+///
+/// This considers `RunInfo.xml` (start of the run), the cycle directories, and the InterOp
+/// files.
+pub fn find_last_activity(path: &Path) -> Option<SystemTime> {
+    let patterns = [
+        format!("{}/RunInfo.xml", path.display()),
+        format!("{}/Data/Intensities/BaseCalls/L*/C*.1", path.display()),
+        format!("{}/InterOp/*.bin", path.display()),
+    ];
+    patterns
+        .iter()
+        .filter_map(|pattern| glob(pattern).ok())
+        .flat_map(|paths| paths.filter_map(|p| p.ok()))
+        .filter_map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// Return the point of time of the last activity if the run in `path` is stalled.
+///
+/// This is the case if `timeout` hours passed since the last activity and there is no
+/// `RTAComplete.txt`.  Stall detection is disabled if `timeout` is not positive.
+pub fn find_stall(path: &Path, timeout: f64) -> Option<SystemTime> {
+    if timeout <= 0.0 || path.join("RTAComplete.txt").exists() {
+        return None;
+    }
+    let last_activity = find_last_activity(path)?;
+    let timeout = Duration::from_secs((timeout * 3600.0) as u64);
+    match SystemTime::now().duration_since(last_activity) {
+        Ok(idle) if idle >= timeout => Some(last_activity),
+        _ => None,
+    }
+}
+
+/// Return the highest cycle with a cycle directory `C<cycle>.1` in any lane of the flow cell
+/// directory at `path`, 0 if there is none (e.g., for NextSeq/MiniSeq).
+pub fn find_highest_cycle(path: &Path) -> i32 {
+    let pattern = format!("{}/Data/Intensities/BaseCalls/L*/C*.1", path.display());
+    match glob(&pattern) {
+        Ok(paths) => paths
+            .filter_map(|p| p.ok())
+            .filter_map(|p| {
+                p.file_name()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.trim_start_matches('C').split('.').next())
+                    .and_then(|s| s.parse::<i32>().ok())
+            })
+            .max()
+            .unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+/// Return the number of completed cycles of the flow cell directory at `path`, 0 if unknown.
+///
+/// The InterOp extraction metrics are used if present as they are written when a cycle was
+/// imaged.  Otherwise, the cycle directories are used where the highest one is still being
+/// written to.
+pub fn find_completed_cycles(path: &Path) -> i32 {
+    match load_highest_extracted_cycle(path) {
+        Ok(Some(cycle)) => cycle,
+        _ => cmp::max(0, find_highest_cycle(path) - 1),
+    }
+}
+
+/// Return `reads` truncated to the first `num_cycles` cycles, dropping reads without cycles.
+pub fn truncate_reads(reads: &[ReadDescription], num_cycles: i32) -> Vec<ReadDescription> {
+    let mut remaining = num_cycles;
+    let mut result = Vec::new();
+    for read in reads {
+        if remaining <= 0 {
+            break;
+        }
+        result.push(ReadDescription {
+            number: read.number,
+            num_cycles: cmp::min(read.num_cycles, remaining),
+            is_index: read.is_index,
+        });
+        remaining -= read.num_cycles;
     }
+    result
 }
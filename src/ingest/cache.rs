@@ -0,0 +1,219 @@
+//! Local state cache for skipping flow cell directories that did not change.
+//!
+//! The cache is stored as a JSON file and records a fingerprint of each processed flow cell
+//! directory together with the state of the flow cell in the API after processing.
+
+use serde_json;
+use shellexpand;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::super::errors::*;
+use super::bcl_meta::find_highest_cycle;
+use super::status::Status;
+use settings::Settings;
+
+/// Files whose modification times are part of the fingerprint.
+static FINGERPRINT_FILES: &[&str] = &[
+    "RunInfo.xml",
+    "runParameters.xml",
+    "RunParameters.xml",
+    "RTAComplete.txt",
+    "CopyComplete.txt",
+    "RunCompletionStatus.xml",
+];
+
+/// Fingerprint of a flow cell directory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// Modification times of the existing `FINGERPRINT_FILES` in milliseconds since the epoch.
+    pub mtimes: BTreeMap<String, u64>,
+    /// Highest cycle with a cycle directory.
+    pub highest_cycle: i32,
+}
+
+impl Fingerprint {
+    /// Compute fingerprint of flow cell directory at `path`.
+    pub fn new(path: &Path) -> Self {
+        let mtimes = FINGERPRINT_FILES
+            .iter()
+            .filter_map(|name| {
+                fs::metadata(path.join(name))
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| {
+                        (
+                            name.to_string(),
+                            d.as_secs() * 1000 + u64::from(d.subsec_millis()),
+                        )
+                    })
+            })
+            .collect();
+
+        Fingerprint {
+            mtimes,
+            highest_cycle: find_highest_cycle(path),
+        }
+    }
+}
+
+/// State of a flow cell directory after it was processed the last time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FolderState {
+    /// Fingerprint of the directory at the time of processing.
+    pub fingerprint: Fingerprint,
+    /// Sequencing status of the flow cell in the API.
+    pub status_sequencing: Status,
+    /// Conversion status of the flow cell in the API, if tracked.
+    #[serde(default)]
+    pub status_conversion: Option<Status>,
+    /// Number of index histograms of the flow cell in the API, if known.
+    pub num_histograms: Option<usize>,
+    /// Number of index histograms expected for the flow cell.
+    pub expected_histograms: usize,
+}
+
+/// The state of all processed flow cell directories, keyed by path.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StateCache {
+    /// Mapping from canonical flow cell directory path to its state.
+    pub folders: BTreeMap<String, FolderState>,
+    /// Mapping from canonical flow cell directory path to the time of the last activity in
+    /// seconds since the epoch that a stall warning was posted for.
+    #[serde(default)]
+    pub stall_warnings: BTreeMap<String, u64>,
+    /// Canonical flow cell directory paths that index pair histograms were posted as a message
+    /// for.
+    #[serde(default)]
+    pub index_pair_messages: BTreeSet<String>,
+    /// Canonical flow cell directory paths that demultiplexing statistics were posted for.
+    #[serde(default)]
+    pub demux_stats: BTreeSet<String>,
+    /// Canonical flow cell directory paths that the run quality summary was posted as a message
+    /// for.
+    #[serde(default)]
+    pub run_metrics_messages: BTreeSet<String>,
+}
+
+impl StateCache {
+    /// Load cache from `path`, empty cache if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(StateCache::default());
+        }
+        let file = File::open(path).chain_err(|| format!("Problem opening cache {:?}", path))?;
+        serde_json::from_reader(file).chain_err(|| format!("Problem reading cache {:?}", path))
+    }
+
+    /// Write cache to `path`, creating the parent directory if necessary.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .chain_err(|| format!("Problem creating cache directory {:?}", parent))?;
+        }
+        // Write to temporary file first so an interrupted write does not destroy the cache.
+        let tmp_path = path.with_extension("json.tmp");
+        {
+            let file = File::create(&tmp_path)
+                .chain_err(|| format!("Problem creating cache {:?}", tmp_path))?;
+            serde_json::to_writer_pretty(file, self)
+                .chain_err(|| format!("Problem writing cache {:?}", tmp_path))?;
+        }
+        fs::rename(&tmp_path, path).chain_err(|| format!("Problem writing cache {:?}", path))
+    }
+
+    /// Return state of flow cell directory at `path`.
+    pub fn get(&self, path: &Path) -> Option<&FolderState> {
+        self.folders.get(&cache_key(path))
+    }
+
+    /// Record state of flow cell directory at `path`.
+    pub fn insert(&mut self, path: &Path, state: FolderState) {
+        self.folders.insert(cache_key(path), state);
+    }
+
+    /// Return the last activity that a stall warning was posted for flow cell directory `path`.
+    pub fn get_stall_warning(&self, path: &Path) -> Option<u64> {
+        self.stall_warnings.get(&cache_key(path)).cloned()
+    }
+
+    /// Record stall warning for the last activity `since` of flow cell directory `path`.
+    pub fn set_stall_warning(&mut self, path: &Path, since: u64) {
+        self.stall_warnings.insert(cache_key(path), since);
+    }
+
+    /// Return whether index pair histograms were posted as a message for flow cell directory
+    /// `path`.
+    pub fn has_index_pair_message(&self, path: &Path) -> bool {
+        self.index_pair_messages.contains(&cache_key(path))
+    }
+
+    /// Record that index pair histograms were posted as a message for flow cell directory `path`.
+    pub fn set_index_pair_message(&mut self, path: &Path) {
+        self.index_pair_messages.insert(cache_key(path));
+    }
+
+    /// Return whether demultiplexing statistics were posted for flow cell directory `path`.
+    pub fn has_demux_stats(&self, path: &Path) -> bool {
+        self.demux_stats.contains(&cache_key(path))
+    }
+
+    /// Record that demultiplexing statistics were posted for flow cell directory `path`.
+    pub fn set_demux_stats(&mut self, path: &Path) {
+        self.demux_stats.insert(cache_key(path));
+    }
+
+    /// Return whether the run quality summary was posted as a message for flow cell directory
+    /// `path`.
+    pub fn has_run_metrics_message(&self, path: &Path) -> bool {
+        self.run_metrics_messages.contains(&cache_key(path))
+    }
+
+    /// Record that the run quality summary was posted as a message for flow cell directory
+    /// `path`.
+    pub fn set_run_metrics_message(&mut self, path: &Path) {
+        self.run_metrics_messages.insert(cache_key(path));
+    }
+}
+
+/// Return key for flow cell directory at `path`, the canonical path if possible.
+fn cache_key(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Return path to the cache file configured in `settings` with `~` expanded.
+fn cache_path(settings: &Settings) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(&settings.ingest.cache_path).into_owned())
+}
+
+/// Load the cache configured in `settings`, falling back to an empty cache on problems.
+pub fn load_state_cache(logger: &slog::Logger, settings: &Settings) -> StateCache {
+    let path = cache_path(settings);
+    debug!(logger, "Loading state cache from {:?}", &path);
+    match StateCache::load(&path) {
+        Ok(cache) => cache,
+        Err(e) => {
+            warn!(logger, "Could not load state cache, starting empty: {}", e);
+            StateCache::default()
+        }
+    }
+}
+
+/// Save `cache` to the file configured in `settings`, problems are only logged.
+pub fn save_state_cache(logger: &slog::Logger, cache: &StateCache, settings: &Settings) {
+    if settings.dry_run {
+        debug!(logger, "Dry run mode active, not writing state cache.");
+        return;
+    }
+    let path = cache_path(settings);
+    debug!(logger, "Writing state cache to {:?}", &path);
+    if let Err(e) = cache.save(&path) {
+        warn!(logger, "Could not write state cache: {}", e);
+    }
+}
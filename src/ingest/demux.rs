@@ -0,0 +1,602 @@
+//! Detection of base call conversion (demultiplexing) output and parsing of its statistics.
+//!
+//! Marker files are configured as glob patterns relative to the flow cell directory.  The
+//! placeholder `{name}` is replaced by the name of the flow cell directory such that output
+//! next to the flow cell directory can be found, e.g., `../{name}_demux/Reports`.
+
+use glob::{glob, Pattern};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use super::super::errors::*;
+use super::status::Status;
+use settings::Settings;
+
+/// Return the paths matching any of `patterns` relative to the flow cell directory `path`.
+pub fn find_markers(path: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let base = Pattern::escape(&path.to_string_lossy());
+
+    let mut result = Vec::new();
+    for pattern in patterns {
+        let pattern = format!(
+            "{}/{}",
+            base,
+            pattern.replace("{name}", &Pattern::escape(&name))
+        );
+        let paths = glob(&pattern).chain_err(|| format!("Invalid marker pattern {:?}", pattern))?;
+        result.extend(paths.filter_map(|p| p.ok()));
+    }
+    Ok(result)
+}
+
+/// Return the conversion status of the flow cell directory at `path` from the markers
+/// configured in `settings`, `None` if there is no conversion output (yet).
+///
+/// Failure markers take precedence over completion markers which take precedence over start
+/// markers.  For completion, the marker paths are returned as well.
+pub fn detect_conversion_status(
+    path: &Path,
+    settings: &Settings,
+) -> Result<Option<(Status, Vec<PathBuf>)>> {
+    let failed = find_markers(path, &settings.ingest.conversion_failed)?;
+    if !failed.is_empty() {
+        return Ok(Some((Status::Failed, failed)));
+    }
+    let complete = find_markers(path, &settings.ingest.conversion_complete)?;
+    if !complete.is_empty() {
+        return Ok(Some((Status::Complete, complete)));
+    }
+    let started = find_markers(path, &settings.ingest.conversion_started)?;
+    if !started.is_empty() {
+        return Ok(Some((Status::InProgress, started)));
+    }
+    Ok(None)
+}
+
+/// Number of unknown barcodes to list in the summary for each lane.
+const SUMMARY_UNKNOWN_BARCODES: usize = 5;
+
+/// Number of reads demultiplexed for one sample in one lane.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SampleReads {
+    /// Sample ID.
+    pub sample: String,
+    /// Index sequence(s), index reads separated by `+`.
+    pub index: String,
+    /// Number of reads (clusters) assigned to the sample.
+    pub num_reads: u64,
+    /// Number of bases assigned to the sample, if known.
+    pub yield_bases: Option<u64>,
+}
+
+/// Number of reads with an index sequence that could not be assigned to any sample.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnknownBarcode {
+    /// Index sequence(s), index reads separated by `+`.
+    pub index: String,
+    /// Number of reads (clusters) with this index.
+    pub num_reads: u64,
+}
+
+/// Demultiplexing statistics of one lane.
+#[derive(Debug, Clone)]
+pub struct LaneDemuxStats {
+    /// Number of the lane.
+    pub lane: i32,
+    /// Reads per sample.
+    pub samples: Vec<SampleReads>,
+    /// Number of reads that could not be assigned to any sample.
+    pub undetermined_reads: u64,
+    /// Number of bases in the lane including undetermined reads, if known.
+    pub yield_bases: Option<u64>,
+    /// Most frequent unknown barcodes, sorted by decreasing number of reads.
+    pub unknown_barcodes: Vec<UnknownBarcode>,
+}
+
+impl LaneDemuxStats {
+    /// Return empty statistics for lane `lane`.
+    fn new(lane: i32) -> Self {
+        LaneDemuxStats {
+            lane,
+            samples: Vec::new(),
+            undetermined_reads: 0,
+            yield_bases: None,
+            unknown_barcodes: Vec::new(),
+        }
+    }
+
+    /// Return the total number of reads in the lane.
+    pub fn total_reads(&self) -> u64 {
+        self.samples.iter().map(|s| s.num_reads).sum::<u64>() + self.undetermined_reads
+    }
+}
+
+/// Demultiplexing statistics of a flow cell.
+#[derive(Debug, Clone)]
+pub struct DemuxStats {
+    /// Path of the file the statistics were read from.
+    pub source: PathBuf,
+    /// Statistics for each lane, sorted by lane.
+    pub lanes: Vec<LaneDemuxStats>,
+}
+
+impl DemuxStats {
+    /// Return human-readable summary for use in a `FlowCellMessage`.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!(
+            "Demultiplexing statistics from {}:\n",
+            self.source.display()
+        )];
+        for lane in &self.lanes {
+            let total = lane.total_reads();
+            let assigned = total - lane.undetermined_reads;
+            let percent = |n: u64| {
+                if total > 0 {
+                    100.0 * n as f64 / total as f64
+                } else {
+                    0.0
+                }
+            };
+            let yield_str = lane
+                .yield_bases
+                .map(|y| format!(", {} bases", y))
+                .unwrap_or_default();
+            lines.push(format!(
+                "- Lane {}: {} reads{}, {} ({:.1}%) assigned to {} samples, {} ({:.1}%) undetermined",
+                lane.lane,
+                total,
+                yield_str,
+                assigned,
+                percent(assigned),
+                lane.samples.len(),
+                lane.undetermined_reads,
+                percent(lane.undetermined_reads)
+            ));
+            for sample in &lane.samples {
+                lines.push(format!(
+                    "  - {} ({}): {} reads ({:.1}%)",
+                    sample.sample,
+                    sample.index,
+                    sample.num_reads,
+                    percent(sample.num_reads)
+                ));
+            }
+            for barcode in lane.unknown_barcodes.iter().take(SUMMARY_UNKNOWN_BARCODES) {
+                lines.push(format!(
+                    "  - unknown barcode {}: {} reads ({:.1}%)",
+                    barcode.index,
+                    barcode.num_reads,
+                    percent(barcode.num_reads)
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Load demultiplexing statistics from the first of `paths` that is a bcl2fastq `Stats.json` or
+/// a BCL Convert `Demultiplex_Stats.csv` file.
+///
+/// For BCL Convert, `Quality_Metrics.csv` and `Top_Unknown_Barcodes.csv` next to the statistics
+/// file are read as well if present.
+pub fn load_demux_stats(paths: &[PathBuf]) -> Result<Option<DemuxStats>> {
+    for path in paths {
+        match path.file_name().and_then(|s| s.to_str()) {
+            Some("Stats.json") => return Ok(Some(load_stats_json(path)?)),
+            Some("Demultiplex_Stats.csv") => return Ok(Some(load_demultiplex_stats_csv(path)?)),
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+/// Sample entry in `DemuxResults` of bcl2fastq `Stats.json`.
+#[derive(Deserialize, Debug)]
+struct Bcl2FastqDemuxResult {
+    #[serde(rename = "SampleId")]
+    sample_id: String,
+    #[serde(rename = "IndexMetrics", default)]
+    index_metrics: Vec<Bcl2FastqIndexMetric>,
+    #[serde(rename = "NumberReads")]
+    number_reads: u64,
+    #[serde(rename = "Yield")]
+    yield_bases: Option<u64>,
+}
+
+/// Entry in `IndexMetrics` of bcl2fastq `Stats.json`.
+#[derive(Deserialize, Debug)]
+struct Bcl2FastqIndexMetric {
+    #[serde(rename = "IndexSequence")]
+    index_sequence: String,
+}
+
+/// `Undetermined` entry of bcl2fastq `Stats.json`.
+#[derive(Deserialize, Debug)]
+struct Bcl2FastqUndetermined {
+    #[serde(rename = "NumberReads")]
+    number_reads: u64,
+}
+
+/// Entry in `UnknownBarcodes` of bcl2fastq `Stats.json`.
+#[derive(Deserialize, Debug)]
+struct Bcl2FastqUnknownBarcodes {
+    #[serde(rename = "Lane")]
+    lane: i32,
+    #[serde(rename = "Barcodes", default)]
+    barcodes: BTreeMap<String, u64>,
+}
+
+/// Entry in `ConversionResults` of bcl2fastq `Stats.json`.
+#[derive(Deserialize, Debug)]
+struct Bcl2FastqConversionResult {
+    #[serde(rename = "LaneNumber")]
+    lane_number: i32,
+    #[serde(rename = "Yield")]
+    yield_bases: Option<u64>,
+    #[serde(rename = "DemuxResults", default)]
+    demux_results: Vec<Bcl2FastqDemuxResult>,
+    #[serde(rename = "Undetermined")]
+    undetermined: Option<Bcl2FastqUndetermined>,
+}
+
+/// The parts of bcl2fastq `Stats.json` that are used.
+#[derive(Deserialize, Debug)]
+struct Bcl2FastqStats {
+    #[serde(rename = "ConversionResults")]
+    conversion_results: Vec<Bcl2FastqConversionResult>,
+    #[serde(rename = "UnknownBarcodes", default)]
+    unknown_barcodes: Vec<Bcl2FastqUnknownBarcodes>,
+}
+
+/// Return `barcodes` as `UnknownBarcode`s sorted by decreasing number of reads.
+fn sorted_unknown_barcodes<I>(barcodes: I) -> Vec<UnknownBarcode>
+where
+    I: IntoIterator<Item = (String, u64)>,
+{
+    let mut result = barcodes
+        .into_iter()
+        .map(|(index, num_reads)| UnknownBarcode { index, num_reads })
+        .collect::<Vec<UnknownBarcode>>();
+    result.sort_by(|a, b| b.num_reads.cmp(&a.num_reads).then(a.index.cmp(&b.index)));
+    result
+}
+
+/// Load demultiplexing statistics from bcl2fastq `Stats.json` file at `path`.
+pub fn load_stats_json(path: &Path) -> Result<DemuxStats> {
+    let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+    let stats: Bcl2FastqStats =
+        serde_json::from_reader(file).chain_err(|| format!("Problem parsing {:?}", path))?;
+
+    let mut unknown_barcodes = stats
+        .unknown_barcodes
+        .into_iter()
+        .map(|entry| (entry.lane, sorted_unknown_barcodes(entry.barcodes)))
+        .collect::<BTreeMap<i32, Vec<UnknownBarcode>>>();
+    let mut lanes = stats
+        .conversion_results
+        .into_iter()
+        .map(|result| LaneDemuxStats {
+            lane: result.lane_number,
+            samples: result
+                .demux_results
+                .into_iter()
+                .map(|sample| SampleReads {
+                    sample: sample.sample_id,
+                    index: sample
+                        .index_metrics
+                        .into_iter()
+                        .map(|m| m.index_sequence)
+                        .collect::<Vec<String>>()
+                        .join("+"),
+                    num_reads: sample.number_reads,
+                    yield_bases: sample.yield_bases,
+                })
+                .collect(),
+            undetermined_reads: result.undetermined.map_or(0, |u| u.number_reads),
+            yield_bases: result.yield_bases,
+            unknown_barcodes: unknown_barcodes
+                .remove(&result.lane_number)
+                .unwrap_or_default(),
+        })
+        .collect::<Vec<LaneDemuxStats>>();
+    lanes.sort_by_key(|lane| lane.lane);
+
+    Ok(DemuxStats {
+        source: path.to_path_buf(),
+        lanes,
+    })
+}
+
+/// Read CSV file at `path` into records mapping column name to value.
+///
+/// The files written by BCL Convert do not use quoting, so splitting at commas is sufficient.
+fn read_csv_records(path: &Path) -> Result<Vec<BTreeMap<String, String>>> {
+    let contents = fs::read_to_string(path).chain_err(|| format!("Problem reading {:?}", path))?;
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let header = match lines.next() {
+        Some(line) => line
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .collect::<Vec<String>>(),
+        None => bail!("File {:?} is empty", path),
+    };
+    Ok(lines
+        .map(|line| {
+            header
+                .iter()
+                .cloned()
+                .zip(line.split(',').map(|s| s.trim().to_string()))
+                .collect()
+        })
+        .collect())
+}
+
+/// Return value of `column` in CSV `record` from `path`, error if missing.
+fn csv_value<'a>(
+    record: &'a BTreeMap<String, String>,
+    column: &str,
+    path: &Path,
+) -> Result<&'a str> {
+    match record.get(column) {
+        Some(value) => Ok(value),
+        None => bail!("Column {:?} missing in {:?}", column, path),
+    }
+}
+
+/// Return the index sequence(s) of CSV `record` from BCL Convert, joined by `+`.
+///
+/// `Demultiplex_Stats.csv` has an `Index` column with `-` as the separator while
+/// `Top_Unknown_Barcodes.csv` has the columns `index` and `index2`.
+fn csv_index(record: &BTreeMap<String, String>) -> String {
+    if let Some(index) = record.get("Index") {
+        return index.replace('-', "+");
+    }
+    ["index", "index2"]
+        .iter()
+        .filter_map(|column| record.get(*column))
+        .filter(|value| !value.is_empty())
+        .cloned()
+        .collect::<Vec<String>>()
+        .join("+")
+}
+
+/// Parse number in `column` of CSV `record` from `path`.
+fn csv_number(record: &BTreeMap<String, String>, column: &str, path: &Path) -> Result<u64> {
+    let value = csv_value(record, column, path)?;
+    // Yields may be written in floating point notation.
+    match value.parse::<u64>() {
+        Ok(number) => Ok(number),
+        Err(_) => value
+            .parse::<f64>()
+            .map(|number| number as u64)
+            .chain_err(|| {
+                format!(
+                    "Invalid value {:?} of column {:?} in {:?}",
+                    value, column, path
+                )
+            }),
+    }
+}
+
+/// Load unknown barcodes per lane from BCL Convert `Top_Unknown_Barcodes.csv` at `path`.
+pub fn load_top_unknown_barcodes_csv(path: &Path) -> Result<BTreeMap<i32, Vec<UnknownBarcode>>> {
+    let mut barcodes: BTreeMap<i32, Vec<(String, u64)>> = BTreeMap::new();
+    for record in read_csv_records(path)? {
+        let lane = csv_number(&record, "Lane", path)? as i32;
+        let num_reads = csv_number(&record, "# Reads", path)?;
+        barcodes
+            .entry(lane)
+            .or_default()
+            .push((csv_index(&record), num_reads));
+    }
+    Ok(barcodes
+        .into_iter()
+        .map(|(lane, barcodes)| (lane, sorted_unknown_barcodes(barcodes)))
+        .collect())
+}
+
+/// Load yield per lane and sample from BCL Convert `Quality_Metrics.csv` at `path`.
+///
+/// The yields of all reads are summed up.  Undetermined reads are listed as sample
+/// "Undetermined".
+fn load_quality_metrics_csv(path: &Path) -> Result<BTreeMap<(i32, String), u64>> {
+    let mut yields = BTreeMap::new();
+    for record in read_csv_records(path)? {
+        let lane = csv_number(&record, "Lane", path)? as i32;
+        let sample = csv_value(&record, "SampleID", path)?.to_string();
+        *yields.entry((lane, sample)).or_insert(0) += csv_number(&record, "Yield", path)?;
+    }
+    Ok(yields)
+}
+
+/// Load demultiplexing statistics from BCL Convert `Demultiplex_Stats.csv` file at `path`.
+pub fn load_demultiplex_stats_csv(path: &Path) -> Result<DemuxStats> {
+    let mut lanes: BTreeMap<i32, LaneDemuxStats> = BTreeMap::new();
+    for record in read_csv_records(path)? {
+        let lane_no = csv_value(&record, "Lane", path)?
+            .parse::<i32>()
+            .chain_err(|| format!("Invalid lane in {:?}", path))?;
+        let sample = csv_value(&record, "SampleID", path)?.to_string();
+        let num_reads = csv_value(&record, "# Reads", path)?
+            .parse::<u64>()
+            .chain_err(|| format!("Invalid number of reads in {:?}", path))?;
+
+        let lane = lanes
+            .entry(lane_no)
+            .or_insert_with(|| LaneDemuxStats::new(lane_no));
+        if sample == "Undetermined" {
+            lane.undetermined_reads += num_reads;
+        } else {
+            lane.samples.push(SampleReads {
+                sample,
+                index: csv_index(&record),
+                num_reads,
+                yield_bases: None,
+            });
+        }
+    }
+
+    let reports_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let metrics_path = reports_dir.join("Quality_Metrics.csv");
+    if metrics_path.exists() {
+        let yields = load_quality_metrics_csv(&metrics_path)?;
+        for lane in lanes.values_mut() {
+            for sample in &mut lane.samples {
+                sample.yield_bases = yields.get(&(lane.lane, sample.sample.clone())).cloned();
+            }
+            let lane_yields = yields
+                .iter()
+                .filter(|((lane_no, _), _)| *lane_no == lane.lane)
+                .map(|(_, y)| *y)
+                .collect::<Vec<u64>>();
+            if !lane_yields.is_empty() {
+                lane.yield_bases = Some(lane_yields.iter().sum());
+            }
+        }
+    }
+    let unknown_path = reports_dir.join("Top_Unknown_Barcodes.csv");
+    if unknown_path.exists() {
+        let mut unknown_barcodes = load_top_unknown_barcodes_csv(&unknown_path)?;
+        for lane in lanes.values_mut() {
+            lane.unknown_barcodes = unknown_barcodes.remove(&lane.lane).unwrap_or_default();
+        }
+    }
+
+    Ok(DemuxStats {
+        source: path.to_path_buf(),
+        lanes: lanes.into_values().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Create empty reports directory for test `name`.
+    fn fixture_dir(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("digestiflow-demux-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn load_stats_json_dual_index() {
+        let dir = fixture_dir("stats-json");
+        let path = dir.join("Stats.json");
+        fs::write(
+            &path,
+            r#"{
+                "Flowcell": "H35TTBGX5",
+                "ConversionResults": [
+                    {
+                        "LaneNumber": 2,
+                        "TotalClustersPF": 300,
+                        "Yield": 9000,
+                        "DemuxResults": [
+                            {
+                                "SampleId": "S2",
+                                "IndexMetrics": [
+                                    {"IndexSequence": "GGGG", "MismatchCounts": {"0": 50}},
+                                    {"IndexSequence": "TTTT", "MismatchCounts": {"0": 50}}
+                                ],
+                                "NumberReads": 100,
+                                "Yield": 3000
+                            }
+                        ],
+                        "Undetermined": {"NumberReads": 200, "Yield": 6000}
+                    },
+                    {
+                        "LaneNumber": 1,
+                        "Yield": 3000,
+                        "DemuxResults": [
+                            {
+                                "SampleId": "S1",
+                                "IndexMetrics": [{"IndexSequence": "ACGT+TGCA"}],
+                                "NumberReads": 100
+                            }
+                        ]
+                    }
+                ],
+                "UnknownBarcodes": [
+                    {"Lane": 2, "Barcodes": {"AAAA+CCCC": 20, "CCCC+AAAA": 150}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let stats = load_stats_json(&path).unwrap();
+        assert_eq!(stats.lanes.len(), 2);
+        let lane = &stats.lanes[0];
+        assert_eq!(lane.lane, 1);
+        assert_eq!(lane.samples[0].index, "ACGT+TGCA");
+        assert_eq!(lane.samples[0].yield_bases, None);
+        assert_eq!(lane.undetermined_reads, 0);
+        assert!(lane.unknown_barcodes.is_empty());
+        let lane = &stats.lanes[1];
+        assert_eq!(lane.lane, 2);
+        assert_eq!(lane.samples[0].sample, "S2");
+        assert_eq!(lane.samples[0].index, "GGGG+TTTT");
+        assert_eq!(lane.samples[0].yield_bases, Some(3000));
+        assert_eq!(lane.undetermined_reads, 200);
+        assert_eq!(lane.yield_bases, Some(9000));
+        assert_eq!(lane.total_reads(), 300);
+        assert_eq!(lane.unknown_barcodes[0].index, "CCCC+AAAA");
+        assert_eq!(lane.unknown_barcodes[0].num_reads, 150);
+        assert_eq!(lane.unknown_barcodes[1].index, "AAAA+CCCC");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_demultiplex_stats_csv_with_reports() {
+        let dir = fixture_dir("demultiplex-stats-csv");
+        let path = dir.join("Demultiplex_Stats.csv");
+        fs::write(
+            &path,
+            "Lane,SampleID,Index,# Reads,# Perfect Index Reads,# One Mismatch Index Reads\n\
+             1,S1,ACGT-TGCA,100,90,10\n\
+             1,S2,GGGG-TTTT,50,50,0\n\
+             1,Undetermined,,25,0,0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Quality_Metrics.csv"),
+            "Lane,SampleID,index,index2,ReadNumber,Yield,YieldQ30,QualityScoreSum,Mean Quality Score (PF),% Q30\n\
+             1,S1,ACGT,TGCA,1,1000,900,35000,35.00,0.90\n\
+             1,S1,ACGT,TGCA,2,1000,800,34000,34.00,0.80\n\
+             1,S2,GGGG,TTTT,1,500,450,17500,35.00,0.90\n\
+             1,Undetermined,,,1,2.5e2,200,8000,32.00,0.80\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Top_Unknown_Barcodes.csv"),
+            "Lane,index,index2,# Reads,% of Unknown Barcodes,% of All Reads\n\
+             1,AAAA,CCCC,5,0.2,0.03\n\
+             1,CCCC,AAAA,20,0.8,0.11\n",
+        )
+        .unwrap();
+
+        let stats = load_demultiplex_stats_csv(&path).unwrap();
+        assert_eq!(stats.lanes.len(), 1);
+        let lane = &stats.lanes[0];
+        assert_eq!(lane.samples.len(), 2);
+        assert_eq!(lane.samples[0].sample, "S1");
+        assert_eq!(lane.samples[0].index, "ACGT+TGCA");
+        assert_eq!(lane.samples[0].num_reads, 100);
+        assert_eq!(lane.samples[0].yield_bases, Some(2000));
+        assert_eq!(lane.samples[1].yield_bases, Some(500));
+        assert_eq!(lane.undetermined_reads, 25);
+        assert_eq!(lane.yield_bases, Some(2750));
+        assert_eq!(lane.unknown_barcodes[0].index, "CCCC+AAAA");
+        assert_eq!(lane.unknown_barcodes[1].num_reads, 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
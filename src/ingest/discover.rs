@@ -0,0 +1,141 @@
+//! Discovery of flow cell directories below sequencer output root directories.
+
+use glob::Pattern;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::super::errors::*;
+use settings::Settings;
+
+/// Regular expression for flow cell directory names `YYMMDD_INSTRUMENT_RUN_FLOWCELL`.
+///
+/// NovaSeq X uses an eight digit date `YYYYMMDD`.
+const RUN_FOLDER_NAME: &str = r"^(\d{6}|\d{8})_[A-Za-z0-9-]+_\d+_[A-Za-z0-9-]+$";
+
+/// Configuration and state for searching flow cell directories.
+struct Discovery<'a> {
+    /// Logger to use.
+    logger: &'a slog::Logger,
+    /// Regular expression built from `RUN_FOLDER_NAME`.
+    name_re: Regex,
+    /// Paths matching any of these patterns are skipped.
+    excludes: Vec<Pattern>,
+    /// Maximal depth below the roots to descend to.
+    max_depth: usize,
+    /// Minimal age of flow cell directories in hours, no limit if not positive.
+    min_age: f64,
+    /// Maximal age of flow cell directories in hours, no limit if not positive.
+    max_age: f64,
+    /// Point of time to compute the directory ages relative to.
+    now: SystemTime,
+}
+
+impl<'a> Discovery<'a> {
+    /// Return age of the directory at `path` in hours based on its modification time.
+    fn age_hours(&self, path: &Path) -> f64 {
+        fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|mtime| self.now.duration_since(mtime).ok())
+            .map(|age| age.as_secs() as f64 / 3600.0)
+            .unwrap_or(0.0)
+    }
+
+    /// Return whether the flow cell directory at `path` is within the configured age range.
+    fn age_matches(&self, path: &Path) -> bool {
+        let age = self.age_hours(path);
+        (self.min_age <= 0.0 || age >= self.min_age) && (self.max_age <= 0.0 || age <= self.max_age)
+    }
+
+    /// Search for flow cell directories in `path` at `depth` below the root, append to `result`.
+    fn walk(&self, path: &Path, depth: usize, result: &mut Vec<PathBuf>) {
+        if self.excludes.iter().any(|p| p.matches_path(path)) {
+            debug!(self.logger, "Excluding {:?}", path);
+            return;
+        }
+
+        // Flow cell directories are not searched any further.
+        if path.join("RunInfo.xml").exists() {
+            let name = path
+                .file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !self.name_re.is_match(&name) {
+                debug!(
+                    self.logger,
+                    "Ignoring {:?}, name does not look like a flow cell directory", path
+                );
+            } else if !self.age_matches(path) {
+                debug!(self.logger, "Ignoring {:?}, age is out of range", path);
+            } else {
+                result.push(path.to_path_buf());
+            }
+            return;
+        }
+
+        if depth >= self.max_depth {
+            return;
+        }
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(self.logger, "Could not list directory {:?}: {}", path, e);
+                return;
+            }
+        };
+        let mut children = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<PathBuf>>();
+        children.sort();
+        for child in &children {
+            self.walk(child, depth + 1, result);
+        }
+    }
+}
+
+/// Return the flow cell directories found below `roots` using the discovery options from
+/// `settings`.
+///
+/// Flow cell directories are recognized by their `RunInfo.xml` file and their name
+/// `YYMMDD_INSTRUMENT_RUN_FLOWCELL`.  The search does not descend into flow cell directories.
+pub fn discover_run_folders(
+    logger: &slog::Logger,
+    roots: &[String],
+    settings: &Settings,
+) -> Result<Vec<PathBuf>> {
+    let excludes = settings
+        .ingest
+        .exclude
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).chain_err(|| format!("Invalid exclude pattern {:?}", pattern))
+        })
+        .collect::<Result<Vec<Pattern>>>()?;
+    let discovery = Discovery {
+        logger,
+        name_re: Regex::new(RUN_FOLDER_NAME).unwrap(),
+        excludes,
+        max_depth: settings.ingest.max_depth,
+        min_age: settings.ingest.min_age,
+        max_age: settings.ingest.max_age,
+        now: SystemTime::now(),
+    };
+
+    let mut result = Vec::new();
+    for root in roots {
+        info!(
+            logger,
+            "Searching for flow cell directories in {:?}...", root
+        );
+        discovery.walk(Path::new(root), 0, &mut result);
+    }
+    result.sort();
+    result.dedup();
+    info!(logger, "Found {} flow cell directories", result.len());
+
+    Ok(result)
+}
@@ -0,0 +1,702 @@
+//! Reading of Illumina InterOp binary metrics from the `InterOp` directory of flow cells.
+//!
+//! Supported are versions 2 and 3 of `TileMetricsOut.bin` and `ExtractionMetricsOut.bin` as well
+//! as versions 4 to 7 of `QMetricsOut.bin` (versions 2 and 3 of the latter were never written by
+//! instruments in use).  All files start with a version byte and a record size byte, followed by
+//! an optional version-specific header and fixed-size little endian records.  Incomplete records
+//! at the end of a file that is still being written are ignored.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use super::super::errors::*;
+use super::bcl_meta::ReadDescription;
+
+/// Metric code of the raw cluster density (clusters per mm²) in v2 tile metrics.
+const TILE_CODE_DENSITY: u16 = 100;
+/// Metric code of the raw cluster count in v2 tile metrics.
+const TILE_CODE_CLUSTERS: u16 = 102;
+/// Metric code of the PF cluster count in v2 tile metrics.
+const TILE_CODE_CLUSTERS_PF: u16 = 103;
+/// Record type of cluster counts in v3 tile metrics.
+const TILE_RECORD_CLUSTERS: u8 = b't';
+
+/// Number of histogram entries in unbinned quality metrics, one per quality value 1..=50.
+const NUM_QUALITIES: usize = 50;
+/// Bases with at least this quality count towards %Q30.
+const MIN_Q30: u8 = 30;
+
+/// An InterOp file loaded into memory.
+struct InterOpFile {
+    /// Path to the file, for error messages.
+    path: String,
+    /// Version of the file format.
+    version: u8,
+    /// Size of each record in bytes.
+    record_size: usize,
+    /// The file's contents.
+    data: Vec<u8>,
+    /// Offset of the first record in `data`, after the header.
+    offset: usize,
+}
+
+impl InterOpFile {
+    /// Load the file at `path` and read the version and record size.
+    fn open(path: &Path) -> Result<Self> {
+        let data = fs::read(path).chain_err(|| format!("Problem reading {:?}", path))?;
+        if data.len() < 2 {
+            bail!("InterOp file {:?} is truncated", path);
+        }
+        Ok(InterOpFile {
+            path: path.display().to_string(),
+            version: data[0],
+            record_size: data[1] as usize,
+            data,
+            offset: 2,
+        })
+    }
+
+    /// Read version-specific header with `f` and advance the offset of the first record.
+    fn read_header<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Cursor<&[u8]>) -> io::Result<T>,
+    {
+        let mut cursor = Cursor::new(&self.data[self.offset..]);
+        let result =
+            f(&mut cursor).chain_err(|| format!("Problem reading header of {}", self.path))?;
+        let offset = self.offset + cursor.position() as usize;
+        if offset > self.data.len() {
+            bail!("Header of {} is truncated", self.path);
+        }
+        self.offset = offset;
+        Ok(result)
+    }
+
+    /// Ensure that the records have at least `min_size` bytes.
+    fn check_record_size(&self, min_size: usize) -> Result<()> {
+        if self.record_size < min_size {
+            bail!(
+                "Record size {} of {} is too small for version {}, expected {}",
+                self.record_size,
+                self.path,
+                self.version,
+                min_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Parse all complete records with `f`.
+    fn parse_records<T, F>(&self, f: F) -> Result<Vec<T>>
+    where
+        F: Fn(&mut Cursor<&[u8]>) -> io::Result<T>,
+    {
+        if self.record_size == 0 {
+            bail!("Record size of {} is zero", self.path);
+        }
+        self.data[self.offset..]
+            .chunks_exact(self.record_size)
+            .map(|record| {
+                f(&mut Cursor::new(record))
+                    .chain_err(|| format!("Problem reading record from {}", self.path))
+            })
+            .collect()
+    }
+
+    /// Return an error about the unsupported version of the file.
+    fn unsupported_version<T>(&self) -> Result<T> {
+        bail!(
+            "Unsupported version {} of InterOp file {}",
+            self.version,
+            self.path
+        )
+    }
+}
+
+/// Cluster information of one tile from `TileMetricsOut.bin`.
+#[derive(Debug, Clone, Default)]
+pub struct TileMetric {
+    /// Number of the lane.
+    pub lane: i32,
+    /// Raw cluster density in clusters per mm², if known.
+    pub density: Option<f64>,
+    /// Raw number of clusters.
+    pub clusters: f64,
+    /// Number of clusters passing filter.
+    pub clusters_pf: f64,
+}
+
+/// Load tile metrics from `TileMetricsOut.bin` at `path`.
+pub fn load_tile_metrics(path: &Path) -> Result<Vec<TileMetric>> {
+    let mut file = InterOpFile::open(path)?;
+    let mut tiles: BTreeMap<(i32, u32), TileMetric> = BTreeMap::new();
+    match file.version {
+        2 => {
+            file.check_record_size(10)?;
+            let records = file.parse_records(|r| {
+                Ok((
+                    r.read_u16::<LittleEndian>()?,
+                    r.read_u16::<LittleEndian>()?,
+                    r.read_u16::<LittleEndian>()?,
+                    r.read_f32::<LittleEndian>()?,
+                ))
+            })?;
+            for (lane, tile, code, value) in records {
+                let lane = i32::from(lane);
+                let tile = u32::from(tile);
+                let metric = tiles.entry((lane, tile)).or_insert_with(|| TileMetric {
+                    lane,
+                    ..TileMetric::default()
+                });
+                match code {
+                    TILE_CODE_DENSITY => metric.density = Some(f64::from(value)),
+                    TILE_CODE_CLUSTERS => metric.clusters = f64::from(value),
+                    TILE_CODE_CLUSTERS_PF => metric.clusters_pf = f64::from(value),
+                    _ => (),
+                }
+            }
+        }
+        3 => {
+            file.check_record_size(15)?;
+            let area = f64::from(file.read_header(|r| r.read_f32::<LittleEndian>())?);
+            let records = file.parse_records(|r| {
+                let lane = r.read_u16::<LittleEndian>()?;
+                let tile = r.read_u32::<LittleEndian>()?;
+                let code = r.read_u8()?;
+                // Read records contain the read number and %aligned instead.
+                let clusters = r.read_f32::<LittleEndian>()?;
+                let clusters_pf = r.read_f32::<LittleEndian>()?;
+                Ok((lane, tile, code, clusters, clusters_pf))
+            })?;
+            for (lane, tile, code, clusters, clusters_pf) in records {
+                if code != TILE_RECORD_CLUSTERS {
+                    continue;
+                }
+                let lane = i32::from(lane);
+                let clusters = f64::from(clusters);
+                tiles.insert(
+                    (lane, tile),
+                    TileMetric {
+                        lane,
+                        density: if area > 0.0 {
+                            Some(clusters / area)
+                        } else {
+                            None
+                        },
+                        clusters,
+                        clusters_pf: f64::from(clusters_pf),
+                    },
+                );
+            }
+        }
+        _ => return file.unsupported_version(),
+    }
+    Ok(tiles.into_values().collect())
+}
+
+/// Number of bases with at least Q30 and in total for one lane and cycle.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CycleQuality {
+    /// Number of bases with quality of at least 30.
+    pub num_q30: u64,
+    /// Number of bases.
+    pub num_total: u64,
+}
+
+/// Load quality metrics from `QMetricsOut.bin` at `path`, summed up for each lane and cycle.
+pub fn load_q_metrics(path: &Path) -> Result<BTreeMap<(i32, i32), CycleQuality>> {
+    let mut file = InterOpFile::open(path)?;
+
+    // Quality value of each histogram entry and whether tile numbers have 32 bits.
+    let unbinned = (1..=NUM_QUALITIES as u8).collect::<Vec<u8>>();
+    let (qualities, wide_tiles) = match file.version {
+        4 => (unbinned, false),
+        5 => {
+            // The bins only describe the remapping, the histogram is not binned.
+            file.read_header(|r| {
+                if r.read_u8()? != 0 {
+                    let num_bins = u64::from(r.read_u8()?);
+                    r.set_position(r.position() + 3 * num_bins);
+                }
+                Ok(())
+            })?;
+            (unbinned, false)
+        }
+        6 | 7 => {
+            let bins = file.read_header(|r| {
+                if r.read_u8()? == 0 {
+                    return Ok(None);
+                }
+                let num_bins = u64::from(r.read_u8()?);
+                // Skip the lower and upper bounds, the bin values follow.
+                r.set_position(r.position() + 2 * num_bins);
+                let mut values = Vec::new();
+                for _ in 0..num_bins {
+                    values.push(r.read_u8()?);
+                }
+                Ok(Some(values))
+            })?;
+            (bins.unwrap_or(unbinned), file.version == 7)
+        }
+        _ => return file.unsupported_version(),
+    };
+    let tile_size = if wide_tiles { 4 } else { 2 };
+    file.check_record_size(4 + tile_size + 4 * qualities.len())?;
+
+    let records = file.parse_records(|r| {
+        let lane = r.read_u16::<LittleEndian>()?;
+        if wide_tiles {
+            r.read_u32::<LittleEndian>()?;
+        } else {
+            r.read_u16::<LittleEndian>()?;
+        }
+        let cycle = r.read_u16::<LittleEndian>()?;
+        let mut quality = CycleQuality::default();
+        for q in &qualities {
+            let count = u64::from(r.read_u32::<LittleEndian>()?);
+            quality.num_total += count;
+            if *q >= MIN_Q30 {
+                quality.num_q30 += count;
+            }
+        }
+        Ok((i32::from(lane), i32::from(cycle), quality))
+    })?;
+
+    let mut result: BTreeMap<(i32, i32), CycleQuality> = BTreeMap::new();
+    for (lane, cycle, quality) in records {
+        let entry = result.entry((lane, cycle)).or_default();
+        entry.num_q30 += quality.num_q30;
+        entry.num_total += quality.num_total;
+    }
+    Ok(result)
+}
+
+/// Load extraction metrics from `ExtractionMetricsOut.bin` at `path`.
+///
+/// Returns the highest extracted cycle for each lane.
+pub fn load_extraction_metrics(path: &Path) -> Result<BTreeMap<i32, i32>> {
+    let mut file = InterOpFile::open(path)?;
+    let wide_tiles = match file.version {
+        2 => {
+            file.check_record_size(38)?;
+            false
+        }
+        3 => {
+            let num_channels = file.read_header(|r| r.read_u8())? as usize;
+            file.check_record_size(8 + 6 * num_channels)?;
+            true
+        }
+        _ => return file.unsupported_version(),
+    };
+
+    let records = file.parse_records(|r| {
+        let lane = r.read_u16::<LittleEndian>()?;
+        if wide_tiles {
+            r.read_u32::<LittleEndian>()?;
+        } else {
+            r.read_u16::<LittleEndian>()?;
+        }
+        let cycle = r.read_u16::<LittleEndian>()?;
+        Ok((i32::from(lane), i32::from(cycle)))
+    })?;
+
+    let mut result = BTreeMap::new();
+    for (lane, cycle) in records {
+        let highest = result.entry(lane).or_insert(0);
+        if cycle > *highest {
+            *highest = cycle;
+        }
+    }
+    Ok(result)
+}
+
+/// Return the highest cycle extracted in any lane of the flow cell directory at `path`.
+///
+/// Returns `None` if there are no extraction metrics (yet).
+pub fn load_highest_extracted_cycle(path: &Path) -> Result<Option<i32>> {
+    let extraction_path = path.join("InterOp").join("ExtractionMetricsOut.bin");
+    if !extraction_path.exists() {
+        return Ok(None);
+    }
+    Ok(load_extraction_metrics(&extraction_path)?
+        .values()
+        .cloned()
+        .max())
+}
+
+/// Quality summary of one read in one lane.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReadQuality {
+    /// Number of the read.
+    pub number: i32,
+    /// Whether this is an index read.
+    pub is_index: bool,
+    /// Percentage of bases with quality of at least 30, `None` if no cycle was called yet.
+    pub percent_q30: Option<f64>,
+}
+
+/// Run quality summary of one lane.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LaneRunMetrics {
+    /// Number of the lane.
+    pub lane: i32,
+    /// Number of tiles with metrics.
+    pub num_tiles: usize,
+    /// Mean raw cluster density over the tiles in clusters per mm².
+    pub density: Option<f64>,
+    /// Raw number of clusters.
+    pub clusters: u64,
+    /// Number of clusters passing filter.
+    pub clusters_pf: u64,
+    /// Percentage of clusters passing filter.
+    pub percent_pf: Option<f64>,
+    /// Number of bases passing filter called so far.
+    pub yield_bases: u64,
+    /// Quality summary for each read.
+    pub reads: Vec<ReadQuality>,
+}
+
+/// Run quality summary of a flow cell.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunMetrics {
+    /// Highest cycle for which extraction metrics are available, 0 if unknown.
+    pub highest_extracted_cycle: i32,
+    /// Summary for each lane.
+    pub lanes: Vec<LaneRunMetrics>,
+}
+
+impl RunMetrics {
+    /// Return message body with a summary of the metrics for each lane.
+    pub fn summary(&self) -> String {
+        let fmt_percent = |value: Option<f64>| match value {
+            Some(value) => format!("{:.1}%", value),
+            None => "n/a".to_string(),
+        };
+        let mut lines = vec![format!(
+            "Run quality summary from InterOp files (extracted up to cycle {}):\n",
+            self.highest_extracted_cycle
+        )];
+        for lane in &self.lanes {
+            lines.push(format!(
+                "- Lane {}: density {}, {} clusters, {} passing filter ({}), {} bases",
+                lane.lane,
+                lane.density
+                    .map(|d| format!("{:.0}/mm²", d))
+                    .unwrap_or_else(|| "n/a".to_string()),
+                lane.clusters,
+                lane.clusters_pf,
+                fmt_percent(lane.percent_pf),
+                lane.yield_bases
+            ));
+            for read in &lane.reads {
+                lines.push(format!(
+                    "  - Read {}{}: {} >= Q30",
+                    read.number,
+                    if read.is_index { " (index)" } else { "" },
+                    fmt_percent(read.percent_q30)
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Return percentage of `part` in `total`, `None` if `total` is zero.
+fn percent(part: f64, total: f64) -> Option<f64> {
+    if total > 0.0 {
+        Some(100.0 * part / total)
+    } else {
+        None
+    }
+}
+
+/// Load run quality summary from the InterOp files in the flow cell directory at `path`.
+///
+/// The cycles are assigned to the reads in `reads`.  Returns `None` if there are no tile metrics
+/// (yet).  Quality and extraction metrics are optional.
+pub fn load_run_metrics(path: &Path, reads: &[ReadDescription]) -> Result<Option<RunMetrics>> {
+    let interop_dir = path.join("InterOp");
+    let tile_path = interop_dir.join("TileMetricsOut.bin");
+    if !tile_path.exists() {
+        return Ok(None);
+    }
+    let tiles = load_tile_metrics(&tile_path)?;
+
+    let q_path = interop_dir.join("QMetricsOut.bin");
+    let qualities = if q_path.exists() {
+        load_q_metrics(&q_path)?
+    } else {
+        BTreeMap::new()
+    };
+    let extraction_path = interop_dir.join("ExtractionMetricsOut.bin");
+    let extracted = if extraction_path.exists() {
+        load_extraction_metrics(&extraction_path)?
+    } else {
+        BTreeMap::new()
+    };
+
+    // Cycle range of each read, cycles are numbered from 1.
+    let mut read_cycles = Vec::new();
+    let mut first_cycle = 1;
+    for read in reads {
+        read_cycles.push((read, first_cycle..first_cycle + read.num_cycles));
+        first_cycle += read.num_cycles;
+    }
+
+    let lane_nos = tiles
+        .iter()
+        .map(|tile| tile.lane)
+        .collect::<BTreeSet<i32>>();
+    let lanes = lane_nos
+        .into_iter()
+        .map(|lane| {
+            let lane_tiles = tiles
+                .iter()
+                .filter(|tile| tile.lane == lane)
+                .collect::<Vec<&TileMetric>>();
+            let densities = lane_tiles
+                .iter()
+                .filter_map(|tile| tile.density)
+                .collect::<Vec<f64>>();
+            let clusters = lane_tiles.iter().map(|tile| tile.clusters).sum::<f64>();
+            let clusters_pf = lane_tiles.iter().map(|tile| tile.clusters_pf).sum::<f64>();
+
+            // Count the cycles with quality scores as called, fall back to extraction.
+            let called_cycles = qualities.keys().filter(|(l, _)| *l == lane).count() as u64;
+            let called_cycles = if called_cycles > 0 {
+                called_cycles
+            } else {
+                extracted.get(&lane).cloned().unwrap_or(0) as u64
+            };
+
+            let reads = read_cycles
+                .iter()
+                .map(|(read, cycles)| {
+                    let (num_q30, num_total) = cycles
+                        .clone()
+                        .filter_map(|cycle| qualities.get(&(lane, cycle)))
+                        .fold((0, 0), |(q30, total), q| {
+                            (q30 + q.num_q30, total + q.num_total)
+                        });
+                    ReadQuality {
+                        number: read.number,
+                        is_index: read.is_index,
+                        percent_q30: percent(num_q30 as f64, num_total as f64),
+                    }
+                })
+                .collect();
+
+            LaneRunMetrics {
+                lane,
+                num_tiles: lane_tiles.len(),
+                density: if densities.is_empty() {
+                    None
+                } else {
+                    Some(densities.iter().sum::<f64>() / densities.len() as f64)
+                },
+                clusters: clusters as u64,
+                clusters_pf: clusters_pf as u64,
+                percent_pf: percent(clusters_pf, clusters),
+                yield_bases: clusters_pf as u64 * called_cycles,
+                reads,
+            }
+        })
+        .collect();
+
+    Ok(Some(RunMetrics {
+        highest_extracted_cycle: extracted.values().cloned().max().unwrap_or(0),
+        lanes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// Create empty flow cell directory with `InterOp` sub directory for test `name`.
+    fn fixture_dir(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("digestiflow-interop-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("InterOp")).unwrap();
+        path
+    }
+
+    /// Write v3 tile metrics with 1000 clusters (900 PF) per tile of lane 1 on 2 mm².
+    fn write_tile_metrics_v3(dir: &Path, tiles: &[u32]) {
+        let mut data = vec![3u8, 15];
+        data.write_f32::<LittleEndian>(2.0).unwrap();
+        for tile in tiles {
+            data.write_u16::<LittleEndian>(1).unwrap();
+            data.write_u32::<LittleEndian>(*tile).unwrap();
+            data.write_u8(TILE_RECORD_CLUSTERS).unwrap();
+            data.write_f32::<LittleEndian>(1000.0).unwrap();
+            data.write_f32::<LittleEndian>(900.0).unwrap();
+            // Read record, to be ignored.
+            data.write_u16::<LittleEndian>(1).unwrap();
+            data.write_u32::<LittleEndian>(*tile).unwrap();
+            data.write_u8(b'r').unwrap();
+            data.write_u32::<LittleEndian>(1).unwrap();
+            data.write_f32::<LittleEndian>(50.0).unwrap();
+        }
+        fs::write(dir.join("InterOp").join("TileMetricsOut.bin"), data).unwrap();
+    }
+
+    /// Write unbinned v7 quality metrics with 25 bases at Q10 and 75 at Q35 per tile and cycle.
+    fn write_q_metrics_v7(dir: &Path, tiles: &[u32], num_cycles: u16) {
+        let mut data = vec![7u8, (8 + 4 * NUM_QUALITIES) as u8, 0];
+        for tile in tiles {
+            for cycle in 1..=num_cycles {
+                data.write_u16::<LittleEndian>(1).unwrap();
+                data.write_u32::<LittleEndian>(*tile).unwrap();
+                data.write_u16::<LittleEndian>(cycle).unwrap();
+                for q in 1..=NUM_QUALITIES {
+                    let count = match q {
+                        10 => 25,
+                        35 => 75,
+                        _ => 0,
+                    };
+                    data.write_u32::<LittleEndian>(count).unwrap();
+                }
+            }
+        }
+        fs::write(dir.join("InterOp").join("QMetricsOut.bin"), data).unwrap();
+    }
+
+    #[test]
+    fn load_tile_metrics_v2() {
+        let dir = fixture_dir("tile-v2");
+        let mut data = vec![2u8, 10];
+        for (code, value) in &[
+            (100u16, 200_000.0f32),
+            (102, 1000.0),
+            (103, 800.0),
+            (200, 0.1),
+        ] {
+            data.write_u16::<LittleEndian>(1).unwrap();
+            data.write_u16::<LittleEndian>(1101).unwrap();
+            data.write_u16::<LittleEndian>(*code).unwrap();
+            data.write_f32::<LittleEndian>(*value).unwrap();
+        }
+        let path = dir.join("InterOp").join("TileMetricsOut.bin");
+        fs::write(&path, data).unwrap();
+
+        let tiles = load_tile_metrics(&path).unwrap();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].lane, 1);
+        assert_eq!(tiles[0].density, Some(200_000.0));
+        assert_eq!(tiles[0].clusters, 1000.0);
+        assert_eq!(tiles[0].clusters_pf, 800.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_tile_metrics_v3() {
+        let dir = fixture_dir("tile-v3");
+        write_tile_metrics_v3(&dir, &[1101, 2101]);
+
+        let tiles = load_tile_metrics(&dir.join("InterOp").join("TileMetricsOut.bin")).unwrap();
+        assert_eq!(tiles.len(), 2);
+        for tile in &tiles {
+            assert_eq!(tile.density, Some(500.0));
+            assert_eq!(tile.clusters, 1000.0);
+            assert_eq!(tile.clusters_pf, 900.0);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_q_metrics_binned_v6() {
+        let dir = fixture_dir("q-v6");
+        let bins = [14u8, 21, 27, 32, 36];
+        let mut data = vec![6u8, (6 + 4 * bins.len()) as u8, 1, bins.len() as u8];
+        data.extend_from_slice(&[2, 15, 23, 31, 35]);
+        data.extend_from_slice(&[14, 22, 30, 34, 40]);
+        data.extend_from_slice(&bins);
+        for cycle in 1..=2 {
+            data.write_u16::<LittleEndian>(1).unwrap();
+            data.write_u16::<LittleEndian>(1101).unwrap();
+            data.write_u16::<LittleEndian>(cycle).unwrap();
+            for count in &[0u32, 10, 10, 30, 50] {
+                data.write_u32::<LittleEndian>(*count).unwrap();
+            }
+        }
+        let path = dir.join("InterOp").join("QMetricsOut.bin");
+        fs::write(&path, data).unwrap();
+
+        let qualities = load_q_metrics(&path).unwrap();
+        assert_eq!(qualities.len(), 2);
+        let quality = qualities[&(1, 2)];
+        assert_eq!(quality.num_q30, 80);
+        assert_eq!(quality.num_total, 100);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_extraction_metrics_ignores_partial_record() {
+        let dir = fixture_dir("extraction-v2");
+        let mut data = vec![2u8, 38];
+        for cycle in 1..=9 {
+            data.write_u16::<LittleEndian>(1).unwrap();
+            data.write_u16::<LittleEndian>(1101).unwrap();
+            data.write_u16::<LittleEndian>(cycle).unwrap();
+            data.extend_from_slice(&[0; 32]);
+        }
+        data.extend_from_slice(&[1, 0, 10]);
+        let path = dir.join("InterOp").join("ExtractionMetricsOut.bin");
+        fs::write(&path, data).unwrap();
+
+        let extracted = load_extraction_metrics(&path).unwrap();
+        assert_eq!(extracted.into_iter().collect::<Vec<_>>(), vec![(1, 9)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_unsupported_version() {
+        let dir = fixture_dir("unsupported");
+        let path = dir.join("InterOp").join("TileMetricsOut.bin");
+        fs::write(&path, vec![9u8, 10]).unwrap();
+
+        assert!(load_tile_metrics(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_run_metrics_per_read() {
+        let dir = fixture_dir("run");
+        assert!(load_run_metrics(&dir, &[]).unwrap().is_none());
+
+        write_tile_metrics_v3(&dir, &[1101, 2101]);
+        write_q_metrics_v7(&dir, &[1101, 2101], 3);
+        let reads = vec![
+            ReadDescription {
+                number: 1,
+                num_cycles: 3,
+                is_index: false,
+            },
+            ReadDescription {
+                number: 2,
+                num_cycles: 2,
+                is_index: true,
+            },
+        ];
+
+        let metrics = load_run_metrics(&dir, &reads).unwrap().unwrap();
+        assert_eq!(metrics.lanes.len(), 1);
+        let lane = &metrics.lanes[0];
+        assert_eq!(lane.num_tiles, 2);
+        assert_eq!(lane.clusters, 2000);
+        assert_eq!(lane.clusters_pf, 1800);
+        assert_eq!(lane.percent_pf, Some(90.0));
+        assert_eq!(lane.yield_bases, 3 * 1800);
+        assert_eq!(lane.reads[0].percent_q30, Some(75.0));
+        assert_eq!(lane.reads[1].percent_q30, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,34 +1,76 @@
 //! Implementation of flow cell folder analysis and import.
 
-use restson::RestClient;
-use std::collections::HashMap;
+use chrono::{DateTime, Local};
+use restson::{RestClient, RestPath};
+use serde::Serialize;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 use sxd_document::parser;
 
 use super::errors::*;
 use settings::Settings;
 
-mod api;
-mod bcl_meta;
+pub mod api;
+pub mod bcl_meta;
 use self::bcl_meta::*;
-mod bcl_data;
+pub mod bcl_data;
 use self::bcl_data::*;
+pub mod cache;
+use self::cache::*;
+mod demux;
+use self::demux::*;
+mod interop;
+use self::interop::*;
+pub mod discover;
+pub mod status;
+use self::status::*;
 
 /// Build a flow cell from the meta information in `run_info` and `run_params`.
 ///
 /// When provided, the previous/current status of sequencing can be given in `status_sequencing`.
-fn build_flow_cell(
+/// While sequencing is in progress, `current_reads` only contains the completed cycles if these
+/// are known.  Stalled runs are marked as failed if configured in `settings`.
+pub fn build_flow_cell(
     run_info: &RunInfo,
     run_params: &RunParameters,
     path: &Path,
-    status_sequencing: Option<String>,
+    folder_layout: FolderLayout,
+    status_sequencing: Option<Status>,
     settings: &Settings,
-) -> api::FlowCell {
-    api::FlowCell {
+) -> Result<api::FlowCell> {
+    let stalled = settings.ingest.stall_mark_failed
+        && find_stall(path, settings.ingest.stall_timeout).is_some();
+    let status_sequencing = get_status_sequencing(
+        run_info,
+        run_params,
+        path,
+        folder_layout,
+        status_sequencing.unwrap_or(Status::Initial),
+        stalled,
+    )?;
+    // Keep all reads if the run is not in progress or its progress cannot be determined (yet).
+    let completed_cycles = if status_sequencing == Status::InProgress {
+        find_completed_cycles(path)
+    } else {
+        0
+    };
+    let current_reads = if completed_cycles > 0 {
+        string_description(&truncate_reads(&run_info.reads, completed_cycles))
+    } else {
+        string_description(&run_info.reads)
+    };
+
+    Ok(api::FlowCell {
         sodar_uuid: None,
         run_date: run_info.date.clone(),
         run_number: run_info.run_number,
@@ -36,28 +78,45 @@ fn build_flow_cell(
         vendor_id: run_info.flowcell.clone(),
         label: Some(run_params.experiment_name.clone()),
         num_lanes: run_info.lane_count,
-        rta_version: run_params
-            .rta_version
-            .split('.')
-            .next()
-            .expect("Could not get RTA Version")
-            .parse::<i32>()
-            .expect("Could not parse RTA version as integer"),
+        rta_version: rta_major_version(run_params, folder_layout)?,
         planned_reads: Some(string_description(&run_params.planned_reads)),
-        current_reads: Some(string_description(&run_info.reads)),
+        current_reads: Some(current_reads),
         manual_label: None,
         description: None,
         sequencing_machine: run_info.instrument.clone(),
         operator: Some(settings.ingest.operator.clone()),
-        status_sequencing: get_status_sequencing(
-            run_info,
-            run_params,
-            path,
-            &status_sequencing.unwrap_or("initial".to_string()),
-        ),
-        status_conversion: "initial".to_string(),
-        status_delivery: "initial".to_string(),
+        status_sequencing,
+        status_conversion: Status::Initial,
+        status_delivery: Status::Initial,
         delivery_type: "seq".to_string(),
+    })
+}
+
+/// Post message with `subject` and `body` to `flowcell`.
+fn post_message(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    subject: &str,
+    body: &str,
+    settings: &Settings,
+) -> Result<()> {
+    if let Some(flowcell_uuid) = flowcell.sodar_uuid.clone() {
+        let args = api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid.clone(),
+            flowcell_uuid,
+        };
+        let message = api::FlowCellMessage {
+            subject: Some(subject.to_string()),
+            body: body.to_string(),
+            state: "sent".to_string(),
+        };
+        client
+            .post(&args, &message)
+            .chain_err(|| "Problem posting message")
+    } else {
+        debug!(logger, "Flow cell has no UUID, cannot post message.");
+        Ok(())
     }
 }
 
@@ -68,11 +127,12 @@ fn register_flowcell(
     run_info: &RunInfo,
     run_params: &RunParameters,
     path: &Path,
+    folder_layout: FolderLayout,
     settings: &Settings,
 ) -> Result<api::FlowCell> {
     info!(logger, "Registering flow cell...");
 
-    let flowcell = build_flow_cell(run_info, run_params, path, None, settings);
+    let flowcell = build_flow_cell(run_info, run_params, path, folder_layout, None, settings)?;
     debug!(logger, "Registering flowcell with API as {:?}", &flowcell);
 
     let args = api::ProjectArgs {
@@ -83,28 +143,21 @@ fn register_flowcell(
         .chain_err(|| "Problem registering data")?;
     debug!(logger, "Registered flowcell: {:?}", &flowcell);
 
-    if flowcell.status_sequencing == "failed" {
-        if let Some(flowcell_uuid) = api_flowcell.sodar_uuid.clone() {
+    // Failures because of stalled runs are reported by `check_stall()`.
+    if flowcell.status_sequencing == Status::Failed {
+        if let Some(reason) = get_failure_reason(run_info, run_params, path)? {
             debug!(
                 logger,
                 "Posting message about reasons for flow cell failure."
             );
-            let args = api::ProjectFlowcellArgs {
-                project_uuid: settings.ingest.project_uuid.clone(),
-                flowcell_uuid: flowcell_uuid,
-            };
-            let message = api::FlowCellMessage {
-                subject: Some("Registered flow cell as \"failed\"".to_string()),
-                body: "The main reason for this is presence of the RTAComplete.txt file but the \
-                       indication of performed and planned cycles differ."
-                    .to_string(),
-                state: "sent".to_string(),
-            };
-            client
-                .post(&args, &message)
-                .chain_err(|| "Problem posting message")?;
-        } else {
-            debug!(logger, "Flow cell has no UUID, cannot post message.");
+            post_message(
+                logger,
+                client,
+                &api_flowcell,
+                "Registered flow cell as \"failed\"",
+                &reason,
+                settings,
+            )?;
         }
     }
 
@@ -114,6 +167,7 @@ fn register_flowcell(
 }
 
 /// Register an existing flow cell with the REST API given the information in `run_info` and `run_params`.
+#[allow(clippy::too_many_arguments)]
 fn update_flowcell(
     logger: &slog::Logger,
     client: &mut RestClient,
@@ -121,6 +175,7 @@ fn update_flowcell(
     run_info: &RunInfo,
     run_params: &RunParameters,
     path: &Path,
+    folder_layout: FolderLayout,
     settings: &Settings,
 ) -> Result<api::FlowCell> {
     info!(logger, "Updating flow cell...");
@@ -129,15 +184,16 @@ fn update_flowcell(
         run_info,
         run_params,
         path,
-        Some(flowcell.status_sequencing.clone()),
+        folder_layout,
+        Some(flowcell.status_sequencing),
         settings,
-    );
+    )?;
     debug!(logger, "Rebuilt flowcell is {:?}", &rebuilt_flowcell);
 
     let updated_flowcell = api::FlowCell {
         planned_reads: rebuilt_flowcell.planned_reads.clone(),
         current_reads: rebuilt_flowcell.current_reads.clone(),
-        status_sequencing: rebuilt_flowcell.status_sequencing.clone(),
+        status_sequencing: rebuilt_flowcell.status_sequencing,
         ..flowcell.clone()
     };
     info!(logger, "Updating flow cell via API");
@@ -154,32 +210,613 @@ fn update_flowcell(
         .put_capture(&args, &updated_flowcell)
         .chain_err(|| "Problem updating")?;
 
-    if flowcell.status_sequencing == "failed" && updated_flowcell.status_sequencing == "complete" {
-        if let Some(flowcell_uuid) = updated_flowcell.sodar_uuid.clone() {
-            debug!(logger, "Post message about un-marking as failed.");
-            let args = api::ProjectFlowcellArgs {
-                project_uuid: settings.ingest.project_uuid.clone(),
-                flowcell_uuid: flowcell_uuid,
-            };
-            let message = api::FlowCellMessage {
-                subject: Some("Flow cell not \"failed\" any more".to_string()),
-                body: "Everything looks good now.  Flow cell is marked as complete now and the \
-                       automated client will not update the state any more."
-                    .to_string(),
-                state: "sent".to_string(),
-            };
-            client
-                .post(&args, &message)
-                .chain_err(|| "Problem posting message")?;
+    if flowcell.status_sequencing == Status::Failed
+        && updated_flowcell.status_sequencing == Status::Complete
+    {
+        debug!(logger, "Post message about un-marking as failed.");
+        post_message(
+            logger,
+            client,
+            &updated_flowcell,
+            "Flow cell not \"failed\" any more",
+            "Everything looks good now.  Flow cell is marked as complete now and the automated \
+             client will not update the state any more.",
+            settings,
+        )?;
+    } else if flowcell.status_sequencing != Status::Failed
+        && updated_flowcell.status_sequencing == Status::Failed
+    {
+        // Failures because of stalled runs are reported by `check_stall()`.
+        if let Some(reason) = get_failure_reason(run_info, run_params, path)? {
+            debug!(logger, "Post message about reasons for flow cell failure.");
+            post_message(
+                logger,
+                client,
+                &updated_flowcell,
+                "Flow cell marked as \"failed\"",
+                &reason,
+                settings,
+            )?;
+        }
+    }
+
+    Ok(api_flowcell)
+}
+
+/// Return whether the run in the flow cell directory at `path` is stalled given the timeout in
+/// `settings`.
+pub fn is_stalled(path: &Path, settings: &Settings) -> bool {
+    find_stall(path, settings.ingest.stall_timeout).is_some()
+}
+
+/// Post a warning to `flowcell` if its run in `path` is stalled.
+///
+/// The warnings are recorded in `cache` such that each stall is only reported once.
+fn check_stall(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    path: &Path,
+    cache: &Mutex<StateCache>,
+    settings: &Settings,
+) -> Result<()> {
+    if flowcell.status_sequencing.is_final() {
+        return Ok(());
+    }
+    let last_activity = match find_stall(path, settings.ingest.stall_timeout) {
+        Some(last_activity) => last_activity,
+        None => return Ok(()),
+    };
+    let since = last_activity
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    if cache.lock().unwrap().get_stall_warning(path) == Some(since) {
+        debug!(logger, "Stalled run was already reported.");
+        return Ok(());
+    }
+
+    let idle_hours = SystemTime::now()
+        .duration_since(last_activity)
+        .map(|d| d.as_secs() as f64 / 3600.0)
+        .unwrap_or(0.0);
+    let last_activity = DateTime::<Local>::from(last_activity).format("%Y-%m-%d %H:%M:%S");
+    warn!(
+        logger,
+        "Run appears to be stalled, last activity at {} ({:.1} hours ago)",
+        last_activity,
+        idle_hours
+    );
+    if settings.dry_run {
+        info!(logger, "Dry run mode activated, not posting stall warning.");
+        return Ok(());
+    }
+
+    let mut body = format!(
+        "There were no new cycles or InterOp updates since {} ({:.1} hours ago) but the \
+         RTAComplete.txt file is missing.",
+        last_activity, idle_hours
+    );
+    if flowcell.status_sequencing == Status::Failed {
+        body.push_str("  The flow cell was marked as \"failed\".");
+    }
+    post_message(
+        logger,
+        client,
+        flowcell,
+        "Sequencing appears to be stalled",
+        &body,
+        settings,
+    )?;
+    cache.lock().unwrap().set_stall_warning(path, since);
+    Ok(())
+}
+
+/// Update the conversion status of `flowcell` from the demultiplexing output in `path`.
+///
+/// Once the conversion is complete, the demultiplexing statistics are posted to the flow cell.
+/// This is recorded in `cache` such that the statistics are posted again if this failed before.
+fn update_conversion(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    path: &Path,
+    cache: &Mutex<StateCache>,
+    settings: &Settings,
+) -> Result<api::FlowCell> {
+    let (detected, markers) = match detect_conversion_status(path, settings)? {
+        Some(result) => result,
+        None => {
+            debug!(logger, "No conversion output found.");
+            return Ok(flowcell.clone());
+        }
+    };
+    debug!(logger, "Conversion markers {:?} => {}", &markers, detected);
+
+    let status_conversion =
+        transition(StatusKind::Conversion, flowcell.status_conversion, detected);
+    let args = api::ProjectFlowcellArgs {
+        project_uuid: settings.ingest.project_uuid.clone(),
+        flowcell_uuid: flowcell.sodar_uuid.clone().unwrap(),
+    };
+    let api_flowcell = if status_conversion == flowcell.status_conversion {
+        debug!(
+            logger,
+            "Conversion status stays \"{}\".", flowcell.status_conversion
+        );
+        flowcell.clone()
+    } else if settings.dry_run {
+        info!(
+            logger,
+            "Dry run mode activated, not updating conversion status to \"{}\".", status_conversion
+        );
+        return Ok(flowcell.clone());
+    } else {
+        info!(
+            logger,
+            "Updating conversion status \"{}\" => \"{}\"",
+            flowcell.status_conversion,
+            status_conversion
+        );
+        let updated_flowcell = api::FlowCell {
+            status_conversion,
+            ..flowcell.clone()
+        };
+        client
+            .put_capture(&args, &updated_flowcell)
+            .chain_err(|| "Problem updating conversion status")?
+    };
+
+    if status_conversion == Status::Complete && !cache.lock().unwrap().has_demux_stats(path) {
+        if settings.dry_run {
+            info!(
+                logger,
+                "Dry run mode activated, not posting demultiplexing statistics."
+            );
         } else {
-            debug!(logger, "Flow cell has no UUID, cannot post message.");
+            match load_demux_stats(&markers)? {
+                Some(stats) => {
+                    post_demux_stats(logger, client, &args, &stats, settings)?;
+                    cache.lock().unwrap().set_demux_stats(path);
+                }
+                None => debug!(
+                    logger,
+                    "No demultiplexing statistics found in {:?}", &markers
+                ),
+            }
         }
     }
 
     Ok(api_flowcell)
 }
 
+/// Return the demultiplexing statistics of the flow cell given by `args` from the API, `None` if
+/// the server does not support them.
+fn get_demux_results(
+    client: &mut RestClient,
+    args: &api::ProjectFlowcellArgs,
+) -> Result<Option<Vec<api::LaneDemuxResult>>> {
+    let result: result::Result<api::LaneDemuxResultArray, restson::Error> = client.get(args);
+    match result {
+        Ok(api::LaneDemuxResultArray::Array(results)) => Ok(Some(results)),
+        Err(restson::Error::HttpError(404, _msg)) => Ok(None),
+        Err(e) => bail!(
+            "Could not query demultiplexing statistics from server {:?}",
+            e
+        ),
+    }
+}
+
+/// Post demultiplexing statistics `stats` of the flow cell given by `args`.
+///
+/// A message with a summary is posted to the flow cell.  The statistics are also saved per lane
+/// to the dedicated endpoint if the server supports it, replacing existing statistics of the same
+/// lane such that posting again does not create duplicates.
+fn post_demux_stats(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    args: &api::ProjectFlowcellArgs,
+    stats: &DemuxStats,
+    settings: &Settings,
+) -> Result<()> {
+    info!(logger, "Posting demultiplexing statistics via API");
+    match get_demux_results(client, args)? {
+        Some(existing) => {
+            for lane in &stats.lanes {
+                let result = api::LaneDemuxResult {
+                    sodar_uuid: None,
+                    flowcell: args.flowcell_uuid.clone(),
+                    lane: lane.lane,
+                    source: stats.source.to_string_lossy().into_owned(),
+                    num_reads: lane.total_reads(),
+                    undetermined_reads: lane.undetermined_reads,
+                    yield_bases: lane.yield_bases,
+                    samples: lane.samples.clone(),
+                    unknown_barcodes: lane.unknown_barcodes.clone(),
+                };
+                let matching = existing
+                    .iter()
+                    .filter(|result| result.lane == lane.lane)
+                    .filter_map(|result| result.sodar_uuid.clone())
+                    .collect::<Vec<String>>();
+                save_replacing(
+                    logger,
+                    client,
+                    &result,
+                    &matching,
+                    &args.flowcell_uuid,
+                    settings,
+                )
+                .chain_err(|| "Problem saving demultiplexing statistics")?;
+            }
+        }
+        None => info!(
+            logger,
+            "Server does not support demultiplexing statistics, only posting message."
+        ),
+    }
+
+    let message = api::FlowCellMessage {
+        subject: Some("Base call conversion complete".to_string()),
+        body: stats.summary(),
+        state: "sent".to_string(),
+    };
+    client
+        .post(args, &message)
+        .chain_err(|| "Problem posting message")
+}
+
+/// Post the run quality summary from the InterOp files in `path` for `flowcell`.
+///
+/// Problems reading the InterOp files, e.g., while they are being written, only lead to warnings.
+/// If the server does not support run metrics, the summary is posted as a message once sequencing
+/// is complete.  This is recorded in `cache` such that the message is only posted once.
+fn post_run_metrics(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    run_info: &RunInfo,
+    path: &Path,
+    cache: &Mutex<StateCache>,
+    settings: &Settings,
+) -> Result<()> {
+    info!(logger, "Reading InterOp metrics...");
+    let metrics = match load_run_metrics(path, &run_info.reads) {
+        Ok(Some(metrics)) => metrics,
+        Ok(None) => {
+            info!(
+                logger,
+                "No InterOp tile metrics found, not posting run metrics."
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            warn!(logger, "Could not read InterOp metrics: {}", e);
+            return Ok(());
+        }
+    };
+    for lane in &metrics.lanes {
+        debug!(logger, "Run metrics of lane {}: {:?}", lane.lane, lane);
+    }
+
+    if settings.dry_run {
+        info!(logger, "Dry run mode activated, not posting run metrics.");
+        return Ok(());
+    }
+    let args = api::ProjectFlowcellArgs {
+        project_uuid: settings.ingest.project_uuid.clone(),
+        flowcell_uuid: flowcell.sodar_uuid.clone().unwrap(),
+    };
+    let run_metrics = api::FlowCellRunMetrics {
+        flowcell: args.flowcell_uuid.clone(),
+        highest_extracted_cycle: metrics.highest_extracted_cycle,
+        lanes: metrics.lanes.clone(),
+    };
+    info!(logger, "Updating run metrics via API");
+    match client.put(&args, &run_metrics) {
+        Ok(()) => Ok(()),
+        Err(restson::Error::HttpError(404, _msg)) => {
+            if flowcell.status_sequencing != Status::Complete {
+                info!(
+                    logger,
+                    "Server does not support run metrics, posting message once sequencing is \
+                     complete."
+                );
+                return Ok(());
+            } else if cache.lock().unwrap().has_run_metrics_message(path) {
+                debug!(logger, "Run metrics were posted as message already.");
+                return Ok(());
+            }
+            info!(
+                logger,
+                "Server does not support run metrics, posting message instead."
+            );
+            post_message(
+                logger,
+                client,
+                flowcell,
+                "Run quality summary",
+                &metrics.summary(),
+                settings,
+            )?;
+            cache.lock().unwrap().set_run_metrics_message(path);
+            Ok(())
+        }
+        Err(e) => Err(e).chain_err(|| "Problem updating run metrics"),
+    }
+}
+
+/// Return number of index histograms expected for `flowcell`, one per lane and index read.
+fn expected_histograms(flowcell: &api::FlowCell) -> usize {
+    flowcell.num_lanes as usize
+        * flowcell
+            .planned_reads
+            .as_ref()
+            .map_or(0, |reads| reads.chars().filter(|x| *x == 'B').count())
+}
+
+/// Return the index histograms of the flow cell with `flowcell_uuid` from the API.
+fn get_index_histograms(
+    client: &mut RestClient,
+    flowcell_uuid: &str,
+    settings: &Settings,
+) -> Result<Vec<api::LaneIndexHistogram>> {
+    let hist_arr: api::LaneIndexHistogramArray = client
+        .get(&api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid.clone(),
+            flowcell_uuid: flowcell_uuid.to_string(),
+        })
+        .chain_err(|| "Could not query index histograms from server")?;
+    match hist_arr {
+        api::LaneIndexHistogramArray::Array(hists) => Ok(hists),
+    }
+}
+
+/// Save the histogram or demultiplexing statistics `hist` of the flow cell with `flowcell_uuid` to
+/// the API, replacing the existing entries with the UUIDs `replaced`.
+///
+/// The first existing entry is updated and the others are removed as duplicates.  If there is no
+/// existing entry, `hist` is posted as a new one.
+fn save_replacing<T>(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    hist: &T,
+    replaced: &[String],
+    flowcell_uuid: &str,
+    settings: &Settings,
+) -> Result<()>
+where
+    T: Serialize
+        + fmt::Debug
+        + for<'a> RestPath<&'a api::ProjectFlowcellArgs>
+        + for<'a> RestPath<&'a api::ProjectFlowcellIndexHistoArgs>,
+{
+    let histo_args = |indexhisto_uuid: &str| api::ProjectFlowcellIndexHistoArgs {
+        project_uuid: settings.ingest.project_uuid.clone(),
+        flowcell_uuid: flowcell_uuid.to_string(),
+        indexhisto_uuid: indexhisto_uuid.to_string(),
+    };
+
+    if let Some(indexhisto_uuid) = replaced.first() {
+        debug!(logger, "Replacing {} with {:?}", indexhisto_uuid, hist);
+        client
+            .put(&histo_args(indexhisto_uuid), hist)
+            .chain_err(|| "Could not update entry on server")?;
+    } else {
+        debug!(logger, "Posting {:?}", hist);
+        client
+            .post(
+                &api::ProjectFlowcellArgs {
+                    project_uuid: settings.ingest.project_uuid.clone(),
+                    flowcell_uuid: flowcell_uuid.to_string(),
+                },
+                hist,
+            )
+            .chain_err(|| "Could not save entry on server")?;
+    }
+
+    for indexhisto_uuid in replaced.iter().skip(1) {
+        info!(logger, "Removing duplicate entry {}", indexhisto_uuid);
+        client
+            .delete::<_, T>(&histo_args(indexhisto_uuid))
+            .chain_err(|| "Could not remove duplicate entry on server")?;
+    }
+    Ok(())
+}
+
+/// Save the index histograms in `index_counts` for `flowcell` to the API, computed with
+/// `min_index_fraction`.
+///
+/// Existing histograms for the same lane and index read are replaced, such that saving the
+/// histograms again does not create duplicates.  Returns
+/// the number of histograms saved.
+pub fn save_index_counts(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    index_counts: &[IndexCounts],
+    min_index_fraction: f64,
+    settings: &Settings,
+) -> Result<usize> {
+    let flowcell_uuid = match flowcell.sodar_uuid.clone() {
+        Some(flowcell_uuid) => flowcell_uuid,
+        None => bail!("Flow cell has no UUID, cannot save index histograms"),
+    };
+    let existing = get_index_histograms(client, &flowcell_uuid, settings)?;
+    info!(
+        logger,
+        "Updating adapter information via API {:?}", &flowcell
+    );
+    for index_info in index_counts {
+        let api_hist = api::LaneIndexHistogram {
+            sodar_uuid: None,
+            flowcell: flowcell_uuid.clone(),
+            lane: index_info.lane_no,
+            index_read_no: index_info.index_no,
+            min_index_fraction,
+            sample_size: index_info.sample_size,
+            pf_filtered: index_info.pf_filtered,
+            histogram: index_info.hist.clone(),
+        };
+        let matching = existing
+            .iter()
+            .filter(|hist| {
+                hist.lane == index_info.lane_no && hist.index_read_no == index_info.index_no
+            })
+            .filter_map(|hist| hist.sodar_uuid.clone())
+            .collect::<Vec<String>>();
+        save_replacing(
+            logger,
+            client,
+            &api_hist,
+            &matching,
+            &flowcell_uuid,
+            settings,
+        )?;
+    }
+    Ok(index_counts.len())
+}
+
+/// Return the existing index pair histograms of the flow cell with `flowcell_uuid`, `None` if the
+/// server does not support them.
+fn get_index_pair_histograms(
+    client: &mut RestClient,
+    flowcell_uuid: &str,
+    settings: &Settings,
+) -> Result<Option<Vec<api::LaneIndexPairHistogram>>> {
+    let result: result::Result<api::LaneIndexPairHistogramArray, restson::Error> =
+        client.get(&api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid.clone(),
+            flowcell_uuid: flowcell_uuid.to_string(),
+        });
+    match result {
+        Ok(api::LaneIndexPairHistogramArray::Array(hists)) => Ok(Some(hists)),
+        Err(restson::Error::HttpError(404, _msg)) => Ok(None),
+        Err(e) => bail!("Could not query index pair histograms from server {:?}", e),
+    }
+}
+
+/// Save the index pair histograms in `index_counts` for `flowcell` to the API, computed with
+/// `min_index_fraction`.
+///
+/// Existing pair histograms for the same lane are replaced.  Returns the number of histograms
+/// saved, `None` if the server does not support index pair histograms.
+pub fn save_index_pair_counts(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    index_counts: &[IndexCounts],
+    min_index_fraction: f64,
+    settings: &Settings,
+) -> Result<Option<usize>> {
+    let flowcell_uuid = match flowcell.sodar_uuid.clone() {
+        Some(flowcell_uuid) => flowcell_uuid,
+        None => bail!("Flow cell has no UUID, cannot save index pair histograms"),
+    };
+    let existing = match get_index_pair_histograms(client, &flowcell_uuid, settings)? {
+        Some(existing) => existing,
+        None => return Ok(None),
+    };
+    info!(logger, "Updating index pair histograms via API");
+    for index_info in index_counts {
+        let api_hist = api::LaneIndexPairHistogram {
+            sodar_uuid: None,
+            flowcell: flowcell_uuid.clone(),
+            lane: index_info.lane_no,
+            min_index_fraction,
+            sample_size: index_info.sample_size,
+            pf_filtered: index_info.pf_filtered,
+            histogram: index_info.hist.clone(),
+        };
+        let matching = existing
+            .iter()
+            .filter(|hist| hist.lane == index_info.lane_no)
+            .filter_map(|hist| hist.sodar_uuid.clone())
+            .collect::<Vec<String>>();
+        save_replacing(
+            logger,
+            client,
+            &api_hist,
+            &matching,
+            &flowcell_uuid,
+            settings,
+        )?;
+    }
+    Ok(Some(index_counts.len()))
+}
+
+/// Compute the index histograms of all index reads of the flow cell directory at `path` without
+/// using the API.
+pub fn analyze_index_reads(
+    logger: &slog::Logger,
+    run_info: &RunInfo,
+    path: &Path,
+    folder_layout: FolderLayout,
+    settings: &Settings,
+) -> Result<Vec<IndexCounts>> {
+    let lanes = (1..=run_info.lane_count).collect::<Vec<i32>>();
+    let mut result = Vec::new();
+    let mut index_no = 0i32;
+    let mut cycle = 1i32;
+    for desc in &run_info.reads {
+        if desc.is_index {
+            index_no += 1;
+            info!(logger, "Analyzing adapters of index read {}...", index_no);
+            result.extend(sample_adapters(
+                logger,
+                path,
+                run_info,
+                desc,
+                folder_layout,
+                settings,
+                index_no,
+                cycle,
+                &lanes,
+            )?);
+        }
+        cycle += desc.num_cycles;
+    }
+    Ok(result)
+}
+
+/// Compute the index pair histograms of the first two index reads of the flow cell directory at
+/// `path` without using the API, empty if there are less than two index reads.
+pub fn analyze_index_pair_reads(
+    logger: &slog::Logger,
+    run_info: &RunInfo,
+    path: &Path,
+    folder_layout: FolderLayout,
+    settings: &Settings,
+) -> Result<Vec<IndexCounts>> {
+    let index_reads = match find_index_pair(&run_info.reads) {
+        Some(index_reads) => index_reads,
+        None => {
+            info!(
+                logger,
+                "Run has less than two index reads, no index pairs to analyze."
+            );
+            return Ok(Vec::new());
+        }
+    };
+    let lanes = (1..=run_info.lane_count).collect::<Vec<i32>>();
+    info!(logger, "Analyzing index pairs...");
+    sample_index_pairs(
+        logger,
+        path,
+        run_info,
+        (index_reads[0].0, index_reads[1].0),
+        (index_reads[0].1, index_reads[1].1),
+        folder_layout,
+        settings,
+        &lanes,
+    )
+}
+
 /// Kick of analyzing the adatpers and then update through API if configured to do so in `settings`.
+///
+/// Only the lanes of each index read without a histogram in the API are analyzed, unless the
+/// analysis is forced.  Returns the number of index histograms of the flow cell in the API
+/// afterwards.
 fn analyze_adapters(
     logger: &slog::Logger,
     flowcell: &api::FlowCell,
@@ -188,98 +825,102 @@ fn analyze_adapters(
     path: &Path,
     folder_layout: FolderLayout,
     settings: &Settings,
-) -> Result<()> {
+) -> Result<usize> {
+    let flowcell_uuid = match flowcell.sodar_uuid.clone() {
+        Some(flowcell_uuid) => flowcell_uuid,
+        None => bail!("Flow cell has no UUID, cannot analyze adapters"),
+    };
+
+    // Get existing index histograms.
+    info!(
+        logger,
+        "Getting existing index histograms for flow cell from API"
+    );
+    let mut present = get_index_histograms(client, &flowcell_uuid, settings)?
+        .iter()
+        .map(|hist| (hist.lane, hist.index_read_no))
+        .collect::<BTreeSet<(i32, i32)>>();
+    info!(
+        logger,
+        "=> flow cell has histograms for {} lanes and index reads already",
+        present.len()
+    );
+    debug!(
+        logger,
+        "expected adapters: {}",
+        expected_histograms(flowcell)
+    );
+
+    // Index reads are only sampled once complete while sequencing is not complete.
+    let completed_cycles = if flowcell.status_sequencing != Status::Complete {
+        Some(find_completed_cycles(path))
+    } else {
+        None
+    };
+
     let mut index_no = 0i32;
     let mut cycle = 1i32; // always throw away first cycle
     for ref desc in &run_info.reads {
         if desc.is_index {
             index_no += 1;
 
-            // Get existing index histograms.
-            info!(
-                logger,
-                "Getting existing index histograms for flow cell from API"
-            );
-            let hist_arr: api::LaneIndexHistogramArray = client
-                .get(&api::ProjectFlowcellArgs {
-                    project_uuid: settings.ingest.project_uuid.clone(),
-                    flowcell_uuid: flowcell.sodar_uuid.clone().unwrap(),
+            // Lanes to analyze, the ones without histogram for this index read unless forced.
+            let lanes = (1..=flowcell.num_lanes)
+                .filter(|lane_no| {
+                    settings.ingest.force_analyze_adapters
+                        || !present.contains(&(*lane_no, index_no))
                 })
-                .chain_err(|| "Could not query index histograms from server")?;
-            let num_hists = match &hist_arr {
-                api::LaneIndexHistogramArray::Array(hists) => {
-                    info!(
-                        logger,
-                        "=> flow cell has {} histograms already",
-                        hists.len()
-                    );
-                    hists.len()
-                }
-            };
-
-            // Number of adapters that are expected.  Will only analyzes
-            let expected_adapters = flowcell.num_lanes as usize
-                * flowcell
-                    .planned_reads
-                    .clone()
-                    .unwrap()
-                    .chars()
-                    .filter(|x| *x == 'B')
-                    .count();
-            debug!(logger, "expected adapters: {}", expected_adapters);
-
-            if num_hists == expected_adapters && !settings.ingest.force_analyze_adapters {
+                .collect::<Vec<i32>>();
+
+            if completed_cycles.is_some_and(|completed| completed < cycle + desc.num_cycles - 1) {
+                info!(
+                    logger,
+                    "Index read {} is not complete yet, not analyzing adapters.", index_no
+                );
+            } else if lanes.is_empty() {
                 info!(
                     logger,
-                    "There already is the expected number of adapters in the API ({}) \
+                    "There already are histograms for all lanes of index read {} in the API \
                      and you did not force analyzing of adapters. NOT analysing adapters.",
-                    expected_adapters
+                    index_no
                 );
             } else {
-                if num_hists == expected_adapters {
+                if settings.ingest.force_analyze_adapters {
                     info!(logger, "You are enforcing the analysis of adapters regardless of existing ones in API...")
                 }
-                info!(logger, "Analyzing adapters...");
+                info!(
+                    logger,
+                    "Analyzing adapters of index read {} for lanes {:?}...", index_no, &lanes
+                );
                 let index_counts = sample_adapters(
                     logger,
                     path,
-                    &desc,
+                    run_info,
+                    desc,
                     folder_layout,
                     settings,
                     index_no,
                     cycle,
+                    &lanes,
                 )?;
 
                 // Push results to API
                 if settings.dry_run {
                     info!(logger, "Dry run mode active, not updating adapters.",);
                 } else if settings.ingest.post_adapters {
-                    info!(
+                    save_index_counts(
                         logger,
-                        "Updating adapter information via API {:?}", &flowcell
+                        client,
+                        flowcell,
+                        &index_counts,
+                        settings.ingest.min_index_fraction,
+                        settings,
+                    )?;
+                    present.extend(
+                        index_counts
+                            .iter()
+                            .map(|index_info| (index_info.lane_no, index_info.index_no)),
                     );
-                    for (i, index_info) in index_counts.iter().enumerate() {
-                        let lane_no = i + 1;
-                        let api_hist = api::LaneIndexHistogram {
-                            sodar_uuid: None,
-                            flowcell: flowcell.sodar_uuid.clone().unwrap(),
-                            lane: lane_no as i32,
-                            index_read_no: index_no,
-                            min_index_fraction: settings.ingest.min_index_fraction,
-                            sample_size: index_info.sample_size,
-                            histogram: index_info.hist.clone(),
-                        };
-                        debug!(logger, "Posting {:?}", &api_hist);
-                        client
-                            .post(
-                                &api::ProjectFlowcellArgs {
-                                    project_uuid: settings.ingest.project_uuid.clone(),
-                                    flowcell_uuid: flowcell.sodar_uuid.clone().unwrap(),
-                                },
-                                &api_hist,
-                            )
-                            .chain_err(|| "Could not update adapter on server")?
-                    }
                 }
             }
         }
@@ -287,26 +928,210 @@ fn analyze_adapters(
     }
 
     info!(logger, "Done analyzing adapters.");
-    Ok(())
+    Ok(present.len())
 }
 
-/// Process the sequencer output folder at `path` with the given `settings`.
-fn process_folder(
+/// Return the first two index reads of `reads` with their start cycles, `None` if there are less.
+fn find_index_pair(reads: &[ReadDescription]) -> Option<Vec<(&ReadDescription, i32)>> {
+    let mut cycle = 1i32;
+    let mut result = Vec::new();
+    for desc in reads {
+        if desc.is_index && result.len() < 2 {
+            result.push((desc, cycle));
+        }
+        cycle += desc.num_cycles;
+    }
+    if result.len() == 2 {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+/// Return message body listing the index pairs in `index_counts`, most frequent first.
+fn index_pairs_summary(index_counts: &[IndexCounts]) -> String {
+    let mut lines = vec!["Index pair histograms (first+second index read):\n".to_string()];
+    for index_info in index_counts {
+        lines.push(format!(
+            "- Lane {}: {} reads{}",
+            index_info.lane_no,
+            index_info.sample_size,
+            if index_info.pf_filtered {
+                " passing filter"
+            } else {
+                ""
+            }
+        ));
+        let mut pairs = index_info.hist.iter().collect::<Vec<_>>();
+        pairs.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (pair, count) in pairs {
+            lines.push(format!(
+                "  - {}: {} ({:.1}%)",
+                pair,
+                count,
+                100.0 * *count as f64 / cmp::max(1, index_info.sample_size) as f64
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Post the index pair histograms in `index_counts` as a message to `flowcell`, for servers that
+/// do not support index pair histograms.
+pub fn post_index_pairs_message(
     logger: &slog::Logger,
-    path: &Path,
     client: &mut RestClient,
+    flowcell: &api::FlowCell,
+    index_counts: &[IndexCounts],
     settings: &Settings,
 ) -> Result<()> {
-    info!(logger, "Starting to process folder {:?}...", path);
+    info!(
+        logger,
+        "Server does not support index pair histograms, posting message instead."
+    );
+    post_message(
+        logger,
+        client,
+        flowcell,
+        "Index pair histograms",
+        &index_pairs_summary(index_counts),
+        settings,
+    )
+}
 
-    // Ensure that `RunInfo.xml` exists and try to guess folder layout.
-    if !path.join("RunInfo.xml").exists() {
-        error!(
+/// Analyze the joint sequences of the first two index reads and save the histograms through the
+/// API if configured to do so in `settings`.
+///
+/// Only lanes without a pair histogram in the API are analyzed, unless the analysis is forced.
+/// If the server does not support pair histograms, they are posted as a message once.
+#[allow(clippy::too_many_arguments)]
+fn analyze_index_pairs(
+    logger: &slog::Logger,
+    flowcell: &api::FlowCell,
+    client: &mut RestClient,
+    run_info: &RunInfo,
+    path: &Path,
+    folder_layout: FolderLayout,
+    cache: &Mutex<StateCache>,
+    settings: &Settings,
+) -> Result<()> {
+    let index_reads = match find_index_pair(&run_info.reads) {
+        Some(index_reads) => index_reads,
+        None => {
+            info!(
+                logger,
+                "Run has less than two index reads, no index pairs to analyze."
+            );
+            return Ok(());
+        }
+    };
+    let (second, second_start) = index_reads[1];
+    if flowcell.status_sequencing != Status::Complete
+        && find_completed_cycles(path) < second_start + second.num_cycles - 1
+    {
+        info!(
             logger,
-            "Path {:?}/RunInfo.xml does not exist! Skipping directory.", path
+            "Second index read is not complete yet, not analyzing index pairs."
         );
-        bail!("RunInfo.xml missing");
+        return Ok(());
     }
+    let flowcell_uuid = match flowcell.sodar_uuid.clone() {
+        Some(flowcell_uuid) => flowcell_uuid,
+        None => bail!("Flow cell has no UUID, cannot analyze index pairs"),
+    };
+
+    // Get existing index pair histograms, `None` if not supported by the server.
+    info!(
+        logger,
+        "Getting existing index pair histograms for flow cell from API"
+    );
+    let existing = get_index_pair_histograms(client, &flowcell_uuid, settings)?;
+
+    let force = settings.ingest.force_analyze_adapters;
+    let lanes = (1..=flowcell.num_lanes)
+        .filter(|lane_no| match existing {
+            Some(ref hists) => force || !hists.iter().any(|hist| hist.lane == *lane_no),
+            None => force || !cache.lock().unwrap().has_index_pair_message(path),
+        })
+        .collect::<Vec<i32>>();
+    if lanes.is_empty() {
+        info!(
+            logger,
+            "Index pairs were analyzed already and you did not force analyzing of adapters. \
+             NOT analysing index pairs."
+        );
+        return Ok(());
+    }
+
+    info!(logger, "Analyzing index pairs for lanes {:?}...", &lanes);
+    let (first, first_start) = index_reads[0];
+    let index_counts = sample_index_pairs(
+        logger,
+        path,
+        run_info,
+        (first, second),
+        (first_start, second_start),
+        folder_layout,
+        settings,
+        &lanes,
+    )?;
+
+    if settings.dry_run {
+        info!(logger, "Dry run mode active, not updating index pairs.");
+    } else if !settings.ingest.post_adapters {
+        debug!(logger, "Not posting adapters, not updating index pairs.");
+    } else if existing.is_some() {
+        save_index_pair_counts(
+            logger,
+            client,
+            flowcell,
+            &index_counts,
+            settings.ingest.min_index_fraction,
+            settings,
+        )?;
+    } else {
+        post_index_pairs_message(logger, client, flowcell, &index_counts, settings)?;
+        cache.lock().unwrap().set_index_pair_message(path);
+    }
+
+    info!(logger, "Done analyzing index pairs.");
+    Ok(())
+}
+
+/// Create REST API client for the Digestiflow Web instance configured in `settings`.
+pub fn build_client(settings: &Settings) -> Result<RestClient> {
+    let mut client =
+        RestClient::new(&settings.web.url).chain_err(|| "Problem creating REST client")?;
+    client
+        .set_header("Authorization", &format!("Token {}", &settings.web.token))
+        .chain_err(|| "Problem configuring REST client")?;
+    Ok(client)
+}
+
+/// Return whether processing a flow cell directory can be skipped based on its cached `state`.
+///
+/// This is the case if its `fingerprint` did not change, the flow cell has a final sequencing
+/// status (and conversion status if it is to be updated), and all index histograms are present
+/// (if they are to be analyzed).
+fn can_skip_folder(state: &FolderState, fingerprint: &Fingerprint, settings: &Settings) -> bool {
+    let hists_complete = !settings.ingest.analyze_adapters
+        || state.num_histograms.unwrap_or(0) >= state.expected_histograms;
+    let conversion_final = !settings.ingest.update_conversion
+        || state.status_conversion.map(Status::is_final) == Some(true);
+    state.fingerprint == *fingerprint
+        && state.status_sequencing.is_final()
+        && conversion_final
+        && hists_complete
+        && settings.ingest.skip_if_status_final
+        && !settings.ingest.force_analyze_adapters
+}
+
+/// Guess the folder layout of the flow cell directory at `path` and parse its `RunInfo.xml` and
+/// run parameters XML files.
+pub fn load_run_folder(
+    logger: &slog::Logger,
+    path: &Path,
+) -> Result<(FolderLayout, RunInfo, RunParameters)> {
     let folder_layout = match guess_folder_layout(path) {
         Ok(layout) => {
             info!(logger, "Guessed folder layout to be {:?}", layout);
@@ -335,12 +1160,7 @@ fn process_folder(
     let info_doc = info_pkg.as_document();
 
     let param_pkg = {
-        let filename = match folder_layout {
-            FolderLayout::MiSeq => "runParameters.xml",
-            FolderLayout::MiniSeq => "RunParameters.xml",
-            FolderLayout::HiSeqX => "RunParameters.xml",
-            FolderLayout::NovaSeq => "RunParameters.xml",
-        };
+        let filename = folder_layout.run_parameters_file();
         let mut xmlf = File::open(path.join(filename))
             .chain_err(|| format!("Problem reading {}", &filename))?;
         let mut contents = String::new();
@@ -356,6 +1176,49 @@ fn process_folder(
     debug!(logger, "Run info is {:?}", &run_info);
     debug!(logger, "Run params is {:?}", &run_params);
 
+    Ok((folder_layout, run_info, run_params))
+}
+
+/// Process the sequencer output folder at `path` with the given `settings`.
+///
+/// The folder is skipped if `cache` shows that it did not change since it was processed with a
+/// final state the last time.  On success, the state of the folder is recorded in `cache`.
+pub fn process_folder(
+    logger: &slog::Logger,
+    path: &Path,
+    client: &mut RestClient,
+    cache: &Mutex<StateCache>,
+    settings: &Settings,
+) -> Result<()> {
+    info!(logger, "Starting to process folder {:?}...", path);
+
+    // Ensure that `RunInfo.xml` exists and try to guess folder layout.
+    if !path.join("RunInfo.xml").exists() {
+        error!(
+            logger,
+            "Path {:?}/RunInfo.xml does not exist! Skipping directory.", path
+        );
+        bail!("RunInfo.xml missing");
+    }
+
+    // Skip folder without touching the API if nothing changed since it was processed.
+    let fingerprint = Fingerprint::new(path);
+    if settings.ingest.ignore_cache {
+        debug!(logger, "Ignoring state cache as requested.");
+    } else if let Some(state) = cache.lock().unwrap().get(path) {
+        if can_skip_folder(state, &fingerprint, settings) {
+            info!(
+                logger,
+                "Folder {:?} did not change since flow cell was found with final status \
+                 \"{}\". Skipping.",
+                path,
+                state.status_sequencing
+            );
+            return Ok(());
+        }
+    }
+    let (folder_layout, run_info, run_params) = load_run_folder(logger, path)?;
+
     // Try to get the flow cell information from API.
     debug!(logger, "Connecting to \"{}\"", &settings.web.url);
     if settings.log_token {
@@ -378,19 +1241,15 @@ fn process_folder(
             Ok(flowcell) => {
                 debug!(logger, "Flow cell found with value {:?}", &flowcell);
                 if settings.ingest.update {
-                    if flowcell.status_sequencing != "initial"
-                        && flowcell.status_sequencing != "in_progress"
-                        // try to recover from not yet confirmed failure
-                        && flowcell.status_sequencing != "failed"
-                    {
+                    if flowcell.status_sequencing.is_final() {
                         if settings.dry_run {
                             info!(logger, "Dry running activated, not updating flow cell.",);
                             flowcell
                         } else if settings.ingest.skip_if_status_final {
                             info!(
                                 logger,
-                                "Flowcell has a final sequencing status ({:?}), skippping",
-                                &flowcell.status_sequencing
+                                "Flowcell has a final sequencing status (\"{}\"), skippping",
+                                flowcell.status_sequencing
                             );
                             flowcell
                         } else {
@@ -400,8 +1259,9 @@ fn process_folder(
                                 &flowcell,
                                 &run_info,
                                 &run_params,
-                                &path,
-                                &settings,
+                                path,
+                                folder_layout,
+                                settings,
                             )?
                         }
                     } else {
@@ -411,8 +1271,9 @@ fn process_folder(
                             &flowcell,
                             &run_info,
                             &run_params,
-                            &path,
-                            &settings,
+                            path,
+                            folder_layout,
+                            settings,
                         )?
                     }
                 } else {
@@ -430,8 +1291,9 @@ fn process_folder(
                         client,
                         &run_info,
                         &run_params,
-                        &path,
-                        &settings,
+                        path,
+                        folder_layout,
+                        settings,
                     )?;
                     debug!(logger, "Flow cell registered as {:?}", &flowcell);
                     flowcell
@@ -445,32 +1307,135 @@ fn process_folder(
                     return Ok(());
                 }
             }
+            Err(restson::Error::ParseError(e)) => bail!(
+                "Could not interpret flow cell from server, e.g., because of an unknown status: {}",
+                e
+            ),
             _x => bail!("Problem resolving flowcell {:?}", &_x),
         }
     } else {
-        // TODO: improve error handling
-        result.expect("Flowcell not found but we are not supposed to register")
+        match result {
+            Ok(flowcell) => flowcell,
+            Err(restson::Error::HttpError(404, _msg)) => {
+                bail!("Flow cell not found but we are not supposed to register")
+            }
+            Err(e) => bail!("Problem resolving flowcell {:?}", e),
+        }
+    };
+
+    let flowcell = if settings.ingest.update_conversion {
+        update_conversion(logger, client, &flowcell, path, cache, settings)?
+    } else {
+        flowcell
     };
 
+    check_stall(logger, client, &flowcell, path, cache, settings)?;
+
+    if settings.ingest.run_metrics {
+        post_run_metrics(logger, client, &flowcell, &run_info, path, cache, settings)?;
+    }
+
     // Check if we should skip this directory.
-    if settings.ingest.analyze_adapters {
-        analyze_adapters(
+    let num_histograms = if settings.ingest.analyze_adapters {
+        Some(analyze_adapters(
             logger,
             &flowcell,
             client,
             &run_info,
-            &path,
+            path,
             folder_layout,
-            &settings,
-        )?;
+            settings,
+        )?)
     } else {
         info!(logger, "You asked me to not analyze adapters.");
+        None
+    };
+
+    if settings.ingest.analyze_index_pairs {
+        analyze_index_pairs(
+            logger,
+            &flowcell,
+            client,
+            &run_info,
+            path,
+            folder_layout,
+            cache,
+            settings,
+        )?;
     }
 
+    cache.lock().unwrap().insert(
+        path,
+        FolderState {
+            fingerprint,
+            status_sequencing: flowcell.status_sequencing,
+            status_conversion: if settings.ingest.update_conversion {
+                Some(flowcell.status_conversion)
+            } else {
+                None
+            },
+            num_histograms,
+            expected_histograms: expected_histograms(&flowcell),
+        },
+    );
+
     info!(logger, "Done processing folder {:?}.", path);
     Ok(())
 }
 
+/// Process the folders at `paths`, up to `settings.ingest.jobs` of them in parallel.
+///
+/// Each worker uses its own REST client and a logger tagged with the folder it processes.  The
+/// state cache is saved after each folder such that progress is kept on termination.  Returns for
+/// each folder whether processing worked.
+pub fn process_folders(
+    logger: &slog::Logger,
+    paths: &[PathBuf],
+    cache: &Mutex<StateCache>,
+    settings: &Settings,
+) -> Vec<bool> {
+    let num_jobs = cmp::max(1, cmp::min(settings.ingest.jobs, paths.len()));
+    debug!(logger, "Processing folders with {} jobs", num_jobs);
+
+    let next_path = AtomicUsize::new(0);
+    let succeeded = Mutex::new(vec![false; paths.len()]);
+    thread::scope(|scope| {
+        for _ in 0..num_jobs {
+            scope.spawn(|| {
+                // The client is created in the worker as it cannot be moved between threads.
+                let mut client = build_client(settings);
+                loop {
+                    let i = next_path.fetch_add(1, Ordering::SeqCst);
+                    if i >= paths.len() {
+                        break;
+                    }
+                    let path = &paths[i];
+                    let logger = logger.new(o!("folder" => path.display().to_string()));
+                    let result = match client {
+                        Ok(ref mut client) => {
+                            process_folder(&logger, path, client, cache, settings)
+                        }
+                        Err(ref e) => Err(format!("Problem creating REST client: {}", e).into()),
+                    };
+                    match result {
+                        Err(e) => warn!(
+                            logger,
+                            "Processing folder {:?} failed. Will go on with other paths but the \
+                             program call will not have return code 0!: {:?}",
+                            &path,
+                            &e
+                        ),
+                        Ok(()) => succeeded.lock().unwrap()[i] = true,
+                    }
+                    save_state_cache(&logger, &cache.lock().unwrap(), settings);
+                }
+            });
+        }
+    });
+
+    succeeded.into_inner().unwrap()
+}
+
 /// Main entry point for the `ingest` command.
 ///
 /// The function will skip folders for which errors occured but only return `Ok(())` if processing
@@ -489,33 +1454,18 @@ pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
     debug!(logger, "Using {} threads", settings.threads);
     env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
 
-    // Create shared client.
-    let mut client = RestClient::new(&settings.web.url).unwrap();
-    client
-        .set_header("Authorization", &format!("Token {}", &settings.web.token))
-        .chain_err(|| "Problem configuring REST client")?;
+    let cache = Mutex::new(load_state_cache(logger, settings));
 
-    let num_failed = settings
-        .ingest
-        .path
+    // Search for flow cell directories if configured to do so.
+    let paths = if settings.ingest.discover {
+        discover::discover_run_folders(logger, &settings.ingest.path, settings)?
+    } else {
+        settings.ingest.path.iter().map(PathBuf::from).collect()
+    };
+
+    let num_failed = process_folders(logger, &paths, &cache, settings)
         .iter()
-        .map(|ref path| {
-            let path = Path::new(path);
-            match process_folder(logger, &path, &mut client, settings) {
-                Err(e) => {
-                    warn!(
-                    logger,
-                    "Processing folder {:?} failed. Will go on with other paths but the program \
-                     call will not have return code 0!: {:?}",
-                    &path,
-                    &e
-                );
-                    true // == any failed
-                }
-                _ => false, // == any failed
-            }
-        })
-        .filter(|failed| *failed)
+        .filter(|ok| !**ok)
         .count();
 
     if num_failed > 0 {
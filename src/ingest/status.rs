@@ -0,0 +1,106 @@
+//! Status values of flow cells and the automatic status transitions the client may perform.
+
+use std::fmt;
+
+/// Status of sequencing, base call conversion, or delivery of a flow cell.
+///
+/// Digestiflow Web uses the same set of values for all three.  Unknown values lead to an error
+/// when deserializing.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Initial,
+    Ready,
+    InProgress,
+    Complete,
+    CompleteWarnings,
+    Failed,
+    Closed,
+    Canceled,
+    Skipped,
+}
+
+impl Status {
+    /// Return the value as used in the API.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Initial => "initial",
+            Status::Ready => "ready",
+            Status::InProgress => "in_progress",
+            Status::Complete => "complete",
+            Status::CompleteWarnings => "complete_warnings",
+            Status::Failed => "failed",
+            Status::Closed => "closed",
+            Status::Canceled => "canceled",
+            Status::Skipped => "skipped",
+        }
+    }
+
+    /// Return whether the status is final, i.e., the client will not update it by itself.
+    ///
+    /// "failed" is not final as the client tries to recover from a not yet confirmed failure.
+    pub fn is_final(self) -> bool {
+        !matches!(
+            self,
+            Status::Initial | Status::Ready | Status::InProgress | Status::Failed
+        )
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The different kinds of status of a flow cell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatusKind {
+    /// `status_sequencing`
+    Sequencing,
+    /// `status_conversion`
+    Conversion,
+}
+
+/// The automatic transitions the client may perform as `(kind, from, to)`.
+static TRANSITIONS: &[(StatusKind, Status, Status)] = &[
+    (StatusKind::Sequencing, Status::Initial, Status::InProgress),
+    (StatusKind::Sequencing, Status::Initial, Status::Complete),
+    (StatusKind::Sequencing, Status::Initial, Status::Failed),
+    // Flow cells may be pre-created in Digestiflow Web with status "ready".
+    (StatusKind::Sequencing, Status::Ready, Status::InProgress),
+    (StatusKind::Sequencing, Status::Ready, Status::Complete),
+    (StatusKind::Sequencing, Status::Ready, Status::Failed),
+    (StatusKind::Sequencing, Status::InProgress, Status::Complete),
+    (StatusKind::Sequencing, Status::InProgress, Status::Failed),
+    // Recover from a failure that was not confirmed yet.
+    (StatusKind::Sequencing, Status::Failed, Status::InProgress),
+    (StatusKind::Sequencing, Status::Failed, Status::Complete),
+    (StatusKind::Conversion, Status::Initial, Status::InProgress),
+    (StatusKind::Conversion, Status::Initial, Status::Complete),
+    (StatusKind::Conversion, Status::Initial, Status::Failed),
+    (StatusKind::Conversion, Status::Ready, Status::InProgress),
+    (StatusKind::Conversion, Status::Ready, Status::Complete),
+    (StatusKind::Conversion, Status::Ready, Status::Failed),
+    (StatusKind::Conversion, Status::InProgress, Status::Complete),
+    (StatusKind::Conversion, Status::InProgress, Status::Failed),
+    // Conversion may be restarted after a failure.
+    (StatusKind::Conversion, Status::Failed, Status::InProgress),
+    (StatusKind::Conversion, Status::Failed, Status::Complete),
+];
+
+/// Return whether the client may automatically change status of `kind` from `from` to `to`.
+pub fn may_transition(kind: StatusKind, from: Status, to: Status) -> bool {
+    from == to || TRANSITIONS.contains(&(kind, from, to))
+}
+
+/// Return the status of `kind` after automatically updating `current` to the `detected` one.
+///
+/// The status is only changed if the transition table allows for it.
+pub fn transition(kind: StatusKind, current: Status, detected: Status) -> Status {
+    if may_transition(kind, current, detected) {
+        detected
+    } else {
+        current
+    }
+}
@@ -0,0 +1,71 @@
+//! Implementation of the `inspect` command.
+//!
+//! The meta data of a flow cell directory is parsed and printed together with the derived
+//! sequencing status and the flow cell that `ingest` would send.  The API is not used.
+
+use serde_json;
+use serde_yaml;
+use std::path::Path;
+
+use super::errors::*;
+use ingest;
+use ingest::api;
+use ingest::bcl_meta::*;
+use ingest::status::Status;
+use settings::Settings;
+
+/// The information parsed and derived from a flow cell directory.
+#[derive(Debug, Serialize)]
+struct Inspection {
+    /// Path to the flow cell directory.
+    path: String,
+    /// The guessed folder layout.
+    folder_layout: FolderLayout,
+    /// Information from `RunInfo.xml`, including the performed reads.
+    run_info: RunInfo,
+    /// Information from the run parameters XML file, including the planned reads.
+    run_params: RunParameters,
+    /// The derived sequencing status.
+    status_sequencing: Status,
+    /// The reason why the run is considered failed, if any.
+    failure_reason: Option<String>,
+    /// The flow cell payload that would be sent to the API.
+    flowcell: api::FlowCell,
+}
+
+/// Main entry point for the `inspect` command.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client inspect");
+    info!(logger, "Options: {:?}", settings);
+
+    let path = Path::new(&settings.inspect.path);
+    if !path.join("RunInfo.xml").exists() {
+        bail!("Path {:?}/RunInfo.xml does not exist!", path);
+    }
+
+    let (folder_layout, run_info, run_params) = ingest::load_run_folder(logger, path)?;
+    let failure_reason = get_failure_reason(&run_info, &run_params, path)?;
+    let flowcell =
+        ingest::build_flow_cell(&run_info, &run_params, path, folder_layout, None, settings)?;
+
+    let inspection = Inspection {
+        path: path.display().to_string(),
+        folder_layout,
+        run_info,
+        run_params,
+        status_sequencing: flowcell.status_sequencing,
+        failure_reason,
+        flowcell,
+    };
+
+    let output = match settings.inspect.format.as_ref() {
+        "json" => {
+            serde_json::to_string_pretty(&inspection).chain_err(|| "Problem converting to JSON")?
+        }
+        "yaml" => serde_yaml::to_string(&inspection).chain_err(|| "Problem converting to YAML")?,
+        format => bail!("Invalid output format: {}", format),
+    };
+    println!("{}", output);
+
+    Ok(())
+}
@@ -17,6 +17,7 @@ extern crate derivative;
 extern crate error_chain;
 extern crate flate2;
 extern crate glob;
+extern crate libc;
 extern crate rand;
 extern crate rand_xorshift;
 extern crate rayon;
@@ -25,6 +26,8 @@ extern crate restson;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
 extern crate shellexpand;
 #[macro_use]
 extern crate slog;
@@ -33,8 +36,13 @@ extern crate slog_term;
 extern crate sxd_document;
 extern crate sxd_xpath;
 
+mod analyze;
 mod ingest;
+mod inspect;
 mod settings;
+mod upload;
+mod validate;
+mod watch;
 
 use slog::Drain;
 
@@ -45,7 +53,15 @@ use std::sync::{atomic, Arc};
 /// Global module with error handlers.
 mod errors {
     // Create the Error, ErrorKind, ResultExt, and Result types
-    error_chain! {}
+    error_chain! {
+        errors {
+            // Missing or malformed value in the meta data XML files of a flow cell directory.
+            Metadata(file: String, xpath: String, message: String) {
+                description("invalid flow cell meta data")
+                display("Invalid meta data in {} at {}: {}", file, xpath, message)
+            }
+        }
+    }
 }
 
 pub use errors::*;
@@ -125,6 +141,31 @@ fn run(matches: ArgMatches) -> Result<()> {
             &Settings::new(&matches).expect("Problem with obtaining configuration"),
         )
         .chain_err(|| "Could not execute 'ingest' command")?,
+        ("watch", Some(_m)) => watch::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'watch' command")?,
+        ("inspect", Some(_m)) => inspect::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'inspect' command")?,
+        ("validate", Some(_m)) => validate::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'validate' command")?,
+        ("analyze", Some(_m)) => analyze::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'analyze' command")?,
+        ("upload", Some(_m)) => upload::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'upload' command")?,
         _ => bail!("Invalid command: {}", matches.subcommand().0),
     }
 
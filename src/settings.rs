@@ -44,9 +44,11 @@ pub struct IngestArgs {
     pub force_analyze_adapters: bool,
     /// Whether or not to post adapter sequence histogram via API.
     pub post_adapters: bool,
+    /// Whether or not to compute joint histograms of the first two index reads.
+    pub analyze_index_pairs: bool,
     /// String to use for machine operator when creating flow cell via API.
     pub operator: String,
-    /// Number of tiles to sample.
+    /// Number of tiles to sample per lane, all tiles if not positive.
     pub sample_tiles: i32,
     /// Number of reads to sample from each tile.
     pub sample_reads_per_tile: i32,
@@ -54,6 +56,43 @@ pub struct IngestArgs {
     pub skip_if_status_final: bool,
     /// Minimum fraction of reads to show an index for index histogram to be computed.
     pub min_index_fraction: f64,
+    /// Minimum (binned) base quality, bases with lower quality are counted as no-call `N`.
+    pub min_base_quality: i32,
+    /// Maximal number of no-calls in an index read for counting it, no limit if negative.
+    pub max_no_calls: i32,
+    /// Whether or not to only count PF clusters.
+    pub filter_pf: bool,
+    /// Whether or not to search for flow cell directories below the given paths.
+    pub discover: bool,
+    /// Maximal depth below the given paths to search for flow cell directories.
+    pub max_depth: usize,
+    /// Glob patterns of paths to skip when searching for flow cell directories.
+    pub exclude: Vec<String>,
+    /// Minimal age of discovered flow cell directories in hours, no limit if not positive.
+    pub min_age: f64,
+    /// Maximal age of discovered flow cell directories in hours, no limit if not positive.
+    pub max_age: f64,
+    /// Path to the JSON file with the state of processed flow cell directories.
+    pub cache_path: String,
+    /// Whether or not to process flow cell directories regardless of the state cache.
+    pub ignore_cache: bool,
+    /// Number of flow cell directories to process in parallel.
+    pub jobs: usize,
+    /// Whether or not to post the run quality summary from the InterOp files.
+    pub run_metrics: bool,
+    /// Whether or not to update the conversion status from demultiplexing output.
+    pub update_conversion: bool,
+    /// Glob patterns of markers for started conversion, relative to the flow cell directory.
+    pub conversion_started: Vec<String>,
+    /// Glob patterns of markers for completed conversion, relative to the flow cell directory.
+    pub conversion_complete: Vec<String>,
+    /// Glob patterns of markers for failed conversion, relative to the flow cell directory.
+    pub conversion_failed: Vec<String>,
+    /// Hours without new cycles or InterOp updates after which a run is stalled, disabled if
+    /// not positive.
+    pub stall_timeout: f64,
+    /// Whether or not to mark stalled runs as failed in addition to posting a warning.
+    pub stall_mark_failed: bool,
 }
 
 impl Default for IngestArgs {
@@ -67,15 +106,131 @@ impl Default for IngestArgs {
             analyze_adapters: true,
             force_analyze_adapters: false,
             post_adapters: true,
+            analyze_index_pairs: false,
             operator: "".to_string(),
             sample_tiles: 1,
             sample_reads_per_tile: 1_000_000,
             skip_if_status_final: true,
             min_index_fraction: 0.001,
+            min_base_quality: 2,
+            max_no_calls: 1,
+            filter_pf: true,
+            discover: false,
+            max_depth: 3,
+            exclude: Vec::new(),
+            min_age: 0.0,
+            max_age: 0.0,
+            cache_path: "~/.cache/digestiflow-cli/state.json".to_string(),
+            ignore_cache: false,
+            jobs: 1,
+            run_metrics: true,
+            update_conversion: false,
+            conversion_started: vec![
+                "Unaligned*".to_string(),
+                "Data/Intensities/BaseCalls/Undetermined_*.fastq.gz".to_string(),
+            ],
+            conversion_complete: vec![
+                "Unaligned*/Stats/Stats.json".to_string(),
+                "Unaligned*/Reports/Demultiplex_Stats.csv".to_string(),
+                "Data/Intensities/BaseCalls/Stats/Stats.json".to_string(),
+            ],
+            conversion_failed: Vec::new(),
+            stall_timeout: 0.0,
+            stall_mark_failed: false,
         };
     }
 }
 
+/// Arguments/configuration for the `watch` command.
+#[derive(Debug, Deserialize)]
+pub struct WatchArgs {
+    /// Vector of root directories to watch for flow cell directories.
+    pub roots: Vec<String>,
+    /// Number of seconds to wait between two scans of the root directories.
+    pub poll_interval: u64,
+}
+
+impl Default for WatchArgs {
+    /// Return defaults for `watch` command arguments.
+    fn default() -> Self {
+        WatchArgs {
+            roots: Vec::new(),
+            poll_interval: 60,
+        }
+    }
+}
+
+/// Arguments/configuration for the `inspect` command.
+#[derive(Debug, Deserialize)]
+pub struct InspectArgs {
+    /// Path of the flow cell directory to inspect.
+    pub path: String,
+    /// Output format, one of `"json"` and `"yaml"`.
+    pub format: String,
+}
+
+impl Default for InspectArgs {
+    /// Return defaults for `inspect` command arguments.
+    fn default() -> Self {
+        InspectArgs {
+            path: "".to_string(),
+            format: "json".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `validate` command.
+#[derive(Debug, Deserialize)]
+pub struct ValidateArgs {
+    /// Path of the flow cell directory to validate.
+    pub path: String,
+    /// Output format of the problem list, one of `"json"` and `"yaml"`.
+    pub format: String,
+}
+
+impl Default for ValidateArgs {
+    /// Return defaults for `validate` command arguments.
+    fn default() -> Self {
+        ValidateArgs {
+            path: "".to_string(),
+            format: "json".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `analyze` command.
+#[derive(Debug, Deserialize)]
+pub struct AnalyzeArgs {
+    /// Path of the flow cell directory to analyze.
+    pub path: String,
+    /// Path of the JSON file to write the index histograms to.
+    pub output: String,
+}
+
+impl Default for AnalyzeArgs {
+    /// Return defaults for `analyze` command arguments.
+    fn default() -> Self {
+        AnalyzeArgs {
+            path: "".to_string(),
+            output: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `upload` command.
+#[derive(Debug, Deserialize)]
+pub struct UploadArgs {
+    /// Paths of the JSON files with index histograms written by `analyze`.
+    pub path: Vec<String>,
+}
+
+impl Default for UploadArgs {
+    /// Return defaults for `upload` command arguments.
+    fn default() -> Self {
+        UploadArgs { path: Vec::new() }
+    }
+}
+
 /// Overall settings.
 #[derive(Debug, Deserialize)]
 pub struct Settings {
@@ -97,6 +252,16 @@ pub struct Settings {
     pub dry_run: bool,
     /// Arguments to the `ingest` command.
     pub ingest: IngestArgs,
+    /// Arguments to the `watch` command.
+    pub watch: WatchArgs,
+    /// Arguments to the `inspect` command.
+    pub inspect: InspectArgs,
+    /// Arguments to the `validate` command.
+    pub validate: ValidateArgs,
+    /// Arguments to the `analyze` command.
+    pub analyze: AnalyzeArgs,
+    /// Arguments to the `upload` command.
+    pub upload: UploadArgs,
 }
 
 impl Default for Settings {
@@ -109,6 +274,11 @@ impl Default for Settings {
             threads: 1,
             web: Web::default(),
             ingest: IngestArgs::default(),
+            watch: WatchArgs::default(),
+            inspect: InspectArgs::default(),
+            validate: ValidateArgs::default(),
+            analyze: AnalyzeArgs::default(),
+            upload: UploadArgs::default(),
             seed: 42,
             log_token: false,
             dry_run: false,
@@ -148,12 +318,43 @@ impl Settings {
                 default.ingest.force_analyze_adapters,
             )?
             .set_default("ingest.post_adapters", default.ingest.post_adapters)?
+            .set_default(
+                "ingest.analyze_index_pairs",
+                default.ingest.analyze_index_pairs,
+            )?
             .set_default("ingest.operator", default.ingest.operator)?
             .set_default("ingest.sample_tiles", default.ingest.sample_tiles as i64)?
             .set_default(
                 "ingest.min_index_fraction",
                 default.ingest.min_index_fraction,
             )?
+            .set_default(
+                "ingest.min_base_quality",
+                default.ingest.min_base_quality as i64,
+            )?
+            .set_default("ingest.max_no_calls", default.ingest.max_no_calls as i64)?
+            .set_default("ingest.filter_pf", default.ingest.filter_pf)?
+            .set_default("ingest.discover", default.ingest.discover)?
+            .set_default("ingest.max_depth", default.ingest.max_depth as i64)?
+            .set_default("ingest.exclude", default.ingest.exclude)?
+            .set_default("ingest.min_age", default.ingest.min_age)?
+            .set_default("ingest.max_age", default.ingest.max_age)?
+            .set_default("ingest.cache_path", default.ingest.cache_path)?
+            .set_default("ingest.ignore_cache", default.ingest.ignore_cache)?
+            .set_default("ingest.jobs", default.ingest.jobs as i64)?
+            .set_default("ingest.run_metrics", default.ingest.run_metrics)?
+            .set_default("ingest.update_conversion", default.ingest.update_conversion)?
+            .set_default(
+                "ingest.conversion_started",
+                default.ingest.conversion_started,
+            )?
+            .set_default(
+                "ingest.conversion_complete",
+                default.ingest.conversion_complete,
+            )?
+            .set_default("ingest.conversion_failed", default.ingest.conversion_failed)?
+            .set_default("ingest.stall_timeout", default.ingest.stall_timeout)?
+            .set_default("ingest.stall_mark_failed", default.ingest.stall_mark_failed)?
             .set_default(
                 "ingest.skip_if_status_final",
                 default.ingest.skip_if_status_final,
@@ -161,7 +362,16 @@ impl Settings {
             .set_default(
                 "ingest.sample_reads_per_tile",
                 default.ingest.sample_reads_per_tile as i64,
-            )?;
+            )?
+            .set_default("watch.roots", default.watch.roots)?
+            .set_default("watch.poll_interval", default.watch.poll_interval as i64)?
+            .set_default("inspect.path", default.inspect.path)?
+            .set_default("inspect.format", default.inspect.format)?
+            .set_default("validate.path", default.validate.path)?
+            .set_default("validate.format", default.validate.format)?
+            .set_default("analyze.path", default.analyze.path)?
+            .set_default("analyze.output", default.analyze.output)?
+            .set_default("upload.path", default.upload.path)?;
 
         // Next, load configuration file.
         let expanded = shellexpand::tilde("~/.digestiflowrc.toml")
@@ -178,27 +388,8 @@ impl Settings {
         // Add settings from command line.
         match matches.subcommand() {
             ("ingest", Some(m)) => {
-                if m.is_present("quiet") {
-                    s.set("quiet", true)?;
-                }
-                if m.is_present("verbose") {
-                    s.set("verbose", true)?;
-                }
-                if m.is_present("dry_run") {
-                    s.set("dry_run", true)?;
-                }
-                if m.is_present("log_token") {
-                    s.set("log_token", true)?;
-                }
-                if m.is_present("threads") {
-                    s.set("threads", m.value_of("threads").unwrap())?;
-                }
-                if m.is_present("web_url") {
-                    s.set("web.url", m.value_of("web_url").unwrap())?;
-                }
-                if m.is_present("project_uuid") {
-                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
-                }
+                merge_global_args(&mut s, m)?;
+                merge_ingest_args(&mut s, m)?;
                 s.set(
                     "ingest.path",
                     m.values_of("path")
@@ -206,37 +397,58 @@ impl Settings {
                         .map(|s| s.to_string())
                         .collect::<Vec<String>>(),
                 )?;
-                if m.is_present("no_register") {
-                    s.set("ingest.register", false)?;
-                }
-                if m.is_present("no_update") {
-                    s.set("ingest.update", false)?;
-                }
-                if m.is_present("analyze_adapters") {
-                    s.set("ingest.analyze_adapters", true)?;
-                }
-                if m.is_present("force_analyze_adapters") {
-                    s.set("ingest.force_analyze_adapters", true)?;
+            }
+            ("watch", Some(m)) => {
+                merge_global_args(&mut s, m)?;
+                merge_ingest_args(&mut s, m)?;
+                s.set(
+                    "watch.roots",
+                    m.values_of("roots")
+                        .expect("Problem getting roots from command line")
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>(),
+                )?;
+                if m.is_present("poll_interval") {
+                    s.set("watch.poll_interval", m.value_of("poll_interval"))?;
                 }
-                if m.is_present("post_adapters") {
-                    s.set("ingest.post_adapters", true)?;
+            }
+            ("inspect", Some(m)) => {
+                merge_global_args(&mut s, m)?;
+                if m.is_present("stall_timeout") {
+                    s.set("ingest.stall_timeout", m.value_of("stall_timeout"))?;
                 }
-                if m.is_present("sample_reads_per_tile") {
-                    s.set(
-                        "ingest.sample_reads_per_tile",
-                        m.value_of("sample_reads_per_tile"),
-                    )?;
+                if m.is_present("stall_mark_failed") {
+                    s.set("ingest.stall_mark_failed", true)?;
                 }
-                if m.is_present("update_if_state_final") {
-                    s.set("ingest.skip_if_status_final", false)?;
+                s.set("inspect.path", m.value_of("path"))?;
+                if m.is_present("format") {
+                    s.set("inspect.format", m.value_of("format"))?;
                 }
-                if m.is_present("min_index_fraction") {
-                    s.set(
-                        "ingest.min_index_fraction",
-                        m.value_of("min_index_fraction"),
-                    )?;
+            }
+            ("validate", Some(m)) => {
+                merge_global_args(&mut s, m)?;
+                s.set("validate.path", m.value_of("path"))?;
+                if m.is_present("format") {
+                    s.set("validate.format", m.value_of("format"))?;
                 }
             }
+            ("analyze", Some(m)) => {
+                merge_global_args(&mut s, m)?;
+                merge_ingest_args(&mut s, m)?;
+                s.set("analyze.path", m.value_of("path"))?;
+                s.set("analyze.output", m.value_of("output"))?;
+            }
+            ("upload", Some(m)) => {
+                merge_global_args(&mut s, m)?;
+                merge_ingest_args(&mut s, m)?;
+                s.set(
+                    "upload.path",
+                    m.values_of("path")
+                        .expect("Problem getting paths from command line")
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>(),
+                )?;
+            }
             _ => {
                 return Err(ConfigError::Message(format!(
                     "Invalid command {}",
@@ -249,3 +461,115 @@ impl Settings {
         s.try_into()
     }
 }
+
+/// Merge the global command line arguments from `m` into `s`.
+fn merge_global_args(s: &mut Config, m: &ArgMatches) -> Result<(), ConfigError> {
+    if m.is_present("quiet") {
+        s.set("quiet", true)?;
+    }
+    if m.is_present("verbose") {
+        s.set("verbose", true)?;
+    }
+    if m.is_present("dry_run") {
+        s.set("dry_run", true)?;
+    }
+    if m.is_present("log_token") {
+        s.set("log_token", true)?;
+    }
+    if m.is_present("threads") {
+        s.set("threads", m.value_of("threads").unwrap())?;
+    }
+    if m.is_present("web_url") {
+        s.set("web.url", m.value_of("web_url").unwrap())?;
+    }
+    Ok(())
+}
+
+/// Merge the command line arguments shared by `ingest` and `watch` from `m` into `s`.
+fn merge_ingest_args(s: &mut Config, m: &ArgMatches) -> Result<(), ConfigError> {
+    if m.is_present("project_uuid") {
+        s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+    }
+    if m.is_present("no_register") {
+        s.set("ingest.register", false)?;
+    }
+    if m.is_present("no_update") {
+        s.set("ingest.update", false)?;
+    }
+    if m.is_present("analyze_adapters") {
+        s.set("ingest.analyze_adapters", true)?;
+    }
+    if m.is_present("force_analyze_adapters") {
+        s.set("ingest.force_analyze_adapters", true)?;
+    }
+    if m.is_present("post_adapters") {
+        s.set("ingest.post_adapters", true)?;
+    }
+    if m.is_present("analyze_index_pairs") {
+        s.set("ingest.analyze_index_pairs", true)?;
+    }
+    if m.is_present("sample_tiles") {
+        s.set("ingest.sample_tiles", m.value_of("sample_tiles"))?;
+    }
+    if m.is_present("sample_reads_per_tile") {
+        s.set(
+            "ingest.sample_reads_per_tile",
+            m.value_of("sample_reads_per_tile"),
+        )?;
+    }
+    if m.is_present("update_if_state_final") {
+        s.set("ingest.skip_if_status_final", false)?;
+    }
+    if m.is_present("min_index_fraction") {
+        s.set(
+            "ingest.min_index_fraction",
+            m.value_of("min_index_fraction"),
+        )?;
+    }
+    if m.is_present("min_base_quality") {
+        s.set("ingest.min_base_quality", m.value_of("min_base_quality"))?;
+    }
+    if m.is_present("max_no_calls") {
+        s.set("ingest.max_no_calls", m.value_of("max_no_calls"))?;
+    }
+    if m.is_present("no_filter_pf") {
+        s.set("ingest.filter_pf", false)?;
+    }
+    if m.is_present("discover") {
+        s.set("ingest.discover", true)?;
+    }
+    if m.is_present("max_depth") {
+        s.set("ingest.max_depth", m.value_of("max_depth"))?;
+    }
+    if let Some(values) = m.values_of("exclude") {
+        s.set(
+            "ingest.exclude",
+            values.map(|s| s.to_string()).collect::<Vec<String>>(),
+        )?;
+    }
+    if m.is_present("min_age") {
+        s.set("ingest.min_age", m.value_of("min_age"))?;
+    }
+    if m.is_present("max_age") {
+        s.set("ingest.max_age", m.value_of("max_age"))?;
+    }
+    if m.is_present("ignore_cache") {
+        s.set("ingest.ignore_cache", true)?;
+    }
+    if m.is_present("jobs") {
+        s.set("ingest.jobs", m.value_of("jobs"))?;
+    }
+    if m.is_present("no_run_metrics") {
+        s.set("ingest.run_metrics", false)?;
+    }
+    if m.is_present("update_conversion") {
+        s.set("ingest.update_conversion", true)?;
+    }
+    if m.is_present("stall_timeout") {
+        s.set("ingest.stall_timeout", m.value_of("stall_timeout"))?;
+    }
+    if m.is_present("stall_mark_failed") {
+        s.set("ingest.stall_mark_failed", true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! Implementation of the `upload` command.
+//!
+//! The index and index pair histograms written by the `analyze` command are posted to the API for
+//! the flow cell with the same sequencing machine, run number, and flow cell vendor ID.  The flow
+//! cell must have been registered before, e.g., using the `ingest` command.  If the server does not
+//! support index pair histograms, they are posted as a message to the flow cell.
+
+use restson::{self, RestClient};
+use serde_json;
+use std::fs::File;
+use std::io::BufReader;
+use std::result;
+
+use super::errors::*;
+use analyze::IndexHistograms;
+use ingest;
+use ingest::api;
+use settings::Settings;
+
+/// Post the index histograms from the JSON file at `path` using `client`.
+fn upload_file(
+    logger: &slog::Logger,
+    client: &mut RestClient,
+    path: &str,
+    settings: &Settings,
+) -> Result<()> {
+    info!(logger, "Reading index histograms from {}...", path);
+    let file = File::open(path).chain_err(|| format!("Problem opening {}", path))?;
+    let histograms: IndexHistograms = serde_json::from_reader(BufReader::new(file))
+        .chain_err(|| format!("Problem reading index histograms from {}", path))?;
+
+    let result: result::Result<api::FlowCell, restson::Error> =
+        client.get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid.clone(),
+            instrument: histograms.instrument.clone(),
+            run_number: histograms.run_number,
+            flowcell: histograms.flowcell.clone(),
+        });
+    let flowcell = match result {
+        Ok(flowcell) => flowcell,
+        Err(restson::Error::HttpError(404, _msg)) => bail!(
+            "Flow cell {} of run {} on {} not found, register it using `ingest` first",
+            &histograms.flowcell,
+            histograms.run_number,
+            &histograms.instrument
+        ),
+        Err(e) => bail!("Problem resolving flowcell {:?}", e),
+    };
+
+    if settings.dry_run {
+        info!(logger, "Dry run mode active, not updating adapters.");
+    } else {
+        let num_hists = ingest::save_index_counts(
+            logger,
+            client,
+            &flowcell,
+            &histograms.index_counts,
+            histograms.min_index_fraction,
+            settings,
+        )?;
+        info!(logger, "Saved {} index histograms.", num_hists);
+
+        if !histograms.index_pair_counts.is_empty() {
+            match ingest::save_index_pair_counts(
+                logger,
+                client,
+                &flowcell,
+                &histograms.index_pair_counts,
+                histograms.min_index_fraction,
+                settings,
+            )? {
+                Some(num_hists) => info!(logger, "Saved {} index pair histograms.", num_hists),
+                None => ingest::post_index_pairs_message(
+                    logger,
+                    client,
+                    &flowcell,
+                    &histograms.index_pair_counts,
+                    settings,
+                )?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Main entry point for the `upload` command.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client upload");
+    info!(logger, "Options: {:?}", settings);
+
+    // Bail out in case of missing project UUID.
+    if settings.ingest.project_uuid.is_empty() {
+        bail!("You have to specify the project UUID");
+    }
+
+    let mut client = ingest::build_client(settings)?;
+    let mut num_failed = 0;
+    for path in &settings.upload.path {
+        if let Err(e) = upload_file(logger, &mut client, path, settings) {
+            warn!(
+                logger,
+                "Uploading {} failed. Will go on with other files but the program call will \
+                 not have return code 0!: {:?}",
+                path,
+                e
+            );
+            num_failed += 1;
+        }
+    }
+
+    if num_failed > 0 {
+        bail!("Uploading of {} files failed!", num_failed);
+    }
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! Implementation of the `validate` command.
+//!
+//! A flow cell directory is checked for completeness and consistency: all cycle directories and
+//! base call files implied by the reads, lanes, and tiles from `RunInfo.xml` must exist, the
+//! CBCL headers must parse, all compressed data must decompress, and the run numbers from the
+//! meta information files must agree.  The API is not used.
+
+use glob::glob;
+use rayon::prelude::*;
+use serde_json;
+use serde_yaml;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use super::errors::*;
+use ingest;
+use ingest::bcl_data::*;
+use ingest::bcl_meta::*;
+use settings::Settings;
+
+/// A problem found in a flow cell directory.
+#[derive(Debug, Serialize)]
+struct Problem {
+    /// The kind of the problem, e.g., `"missing_file"`.
+    kind: &'static str,
+    /// The path of the affected file or directory.
+    path: String,
+    /// Description of the problem.
+    message: String,
+}
+
+impl Problem {
+    /// Construct new problem of `kind` for `path`.
+    fn new(kind: &'static str, path: &Path, message: &str) -> Self {
+        Problem {
+            kind,
+            path: path.display().to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A base call file that exists and whose contents are to be checked.
+enum DataFile {
+    /// BCL file (plain, gzip or BGZF compressed).
+    Bcl(PathBuf),
+    /// CBCL file with the tiles expected in its header, not checked if empty.
+    Cbcl(PathBuf, Vec<u32>),
+}
+
+/// Return the message of `error` including its causes.
+fn error_message(error: &Error) -> String {
+    error
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<String>>()
+        .join(": ")
+}
+
+/// Return the tile numbers of `lane_no` listed in `run_info`, empty if the tiles are not listed.
+fn lane_tiles(run_info: &RunInfo, lane_no: i32) -> Vec<u32> {
+    let prefix = format!("{}_", lane_no);
+    run_info
+        .tiles
+        .iter()
+        .filter(|tile| tile.starts_with(&prefix))
+        .filter_map(|tile| tile[prefix.len()..].parse::<u32>().ok())
+        .collect()
+}
+
+/// Return the file names in `dir` matching the glob `pattern`.
+fn file_names(dir: &Path, pattern: &str) -> Vec<String> {
+    glob(dir.join(pattern).to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .filter_map(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.to_string())
+        })
+        .collect()
+}
+
+/// Collect the base call files of lane `lane_no` in `lane_path` implied by `num_cycles` and the
+/// tiles into `files`, missing ones are recorded in `problems`.
+fn collect_lane_files(
+    folder_layout: FolderLayout,
+    run_info: &RunInfo,
+    lane_no: i32,
+    lane_path: &Path,
+    num_cycles: i32,
+    files: &mut Vec<DataFile>,
+    problems: &mut Vec<Problem>,
+) {
+    let tiles = lane_tiles(run_info, lane_no);
+    let first_cycle = lane_path.join("C1.1");
+
+    // Per tile (MiSeq, HiSeq X) or surface (NovaSeq) file names expected in each cycle
+    // directory, taken from the first cycle if `RunInfo.xml` does not list the tiles.
+    let names: Vec<(Vec<String>, Vec<u32>)> = match folder_layout {
+        FolderLayout::MiniSeq => {
+            for cycle in 1..=num_cycles {
+                let path = lane_path.join(format!("{:04}.bcl.bgzf", cycle));
+                if path.exists() {
+                    files.push(DataFile::Bcl(path));
+                } else {
+                    problems.push(Problem::new("missing_file", &path, "BCL file is missing"));
+                }
+            }
+            return;
+        }
+        FolderLayout::MiSeq | FolderLayout::HiSeqX => {
+            let suffixes: &[&str] = if folder_layout == FolderLayout::HiSeqX {
+                &[".gz"]
+            } else {
+                &["", ".gz"]
+            };
+            let tiles = if tiles.is_empty() {
+                file_names(&first_cycle, &format!("s_{}_*.bcl*", lane_no))
+                    .iter()
+                    .map(|name| tile_from_file_name(name) as u32)
+                    .collect::<BTreeSet<u32>>()
+                    .into_iter()
+                    .collect()
+            } else {
+                tiles
+            };
+            tiles
+                .iter()
+                .map(|tile_no| {
+                    let names = suffixes
+                        .iter()
+                        .map(|suffix| format!("s_{}_{}.bcl{}", lane_no, tile_no, suffix))
+                        .collect();
+                    (names, Vec::new())
+                })
+                .collect()
+        }
+        FolderLayout::NovaSeq | FolderLayout::NovaSeqX => {
+            let surfaces = if tiles.is_empty() {
+                file_names(&first_cycle, &format!("L{:03}_?.cbcl", lane_no))
+                    .iter()
+                    .filter_map(|name| name[5..6].parse::<i32>().ok())
+                    .collect::<BTreeSet<i32>>()
+            } else {
+                tiles
+                    .iter()
+                    .map(|tile_no| tile_surface_swath(*tile_no as i32).0)
+                    .collect::<BTreeSet<i32>>()
+            };
+            surfaces
+                .iter()
+                .map(|surface| {
+                    let surface_tiles = tiles
+                        .iter()
+                        .filter(|tile_no| tile_surface_swath(**tile_no as i32).0 == *surface)
+                        .cloned()
+                        .collect();
+                    (
+                        vec![format!("L{:03}_{}.cbcl", lane_no, surface)],
+                        surface_tiles,
+                    )
+                })
+                .collect()
+        }
+    };
+    if names.is_empty() {
+        problems.push(Problem::new(
+            "missing_file",
+            &first_cycle,
+            "No base call files found",
+        ));
+    }
+
+    for cycle in 1..=num_cycles {
+        let cycle_path = lane_path.join(format!("C{}.1", cycle));
+        if !cycle_path.is_dir() {
+            problems.push(Problem::new(
+                "missing_cycle_dir",
+                &cycle_path,
+                "Cycle directory is missing",
+            ));
+            continue;
+        }
+        for (candidates, tiles) in &names {
+            match candidates
+                .iter()
+                .map(|name| cycle_path.join(name))
+                .find(|path| path.exists())
+            {
+                Some(path) => files.push(if path.extension().unwrap() == "cbcl" {
+                    DataFile::Cbcl(path, tiles.clone())
+                } else {
+                    DataFile::Bcl(path)
+                }),
+                None => problems.push(Problem::new(
+                    "missing_file",
+                    &cycle_path.join(&candidates[0]),
+                    "Base call file is missing",
+                )),
+            }
+        }
+    }
+}
+
+/// Check the contents of the base call file `file` and return the problems found.
+fn check_file(logger: &slog::Logger, file: &DataFile) -> Vec<Problem> {
+    match file {
+        DataFile::Bcl(path) => match check_bcl_data(path.to_str().unwrap()) {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![Problem::new("corrupt_data", path, &error_message(&e))],
+        },
+        DataFile::Cbcl(path, tiles) => {
+            let path_str = path.to_str().unwrap();
+            let header_tiles = match load_cbcl_tiles(logger, path_str) {
+                Ok(header_tiles) => header_tiles,
+                Err(e) => return vec![Problem::new("cbcl_header", path, &error_message(&e))],
+            };
+            let mut problems = tiles
+                .iter()
+                .filter(|tile_no| !header_tiles.contains(tile_no))
+                .map(|tile_no| {
+                    Problem::new(
+                        "cbcl_header",
+                        path,
+                        &format!("Tile {} is missing from header", tile_no),
+                    )
+                })
+                .collect::<Vec<Problem>>();
+            if let Err(e) = check_cbcl_data(logger, path_str) {
+                problems.push(Problem::new("corrupt_data", path, &error_message(&e)));
+            }
+            problems
+        }
+    }
+}
+
+/// Validate the flow cell directory at `path` and return the problems found.
+fn validate_folder(logger: &slog::Logger, path: &Path) -> Vec<Problem> {
+    let (folder_layout, run_info, run_params) = match ingest::load_run_folder(logger, path) {
+        Ok(result) => result,
+        Err(e) => return vec![Problem::new("metadata", path, &error_message(&e))],
+    };
+
+    let mut problems = Vec::new();
+    if run_info.run_number != run_params.run_number {
+        problems.push(Problem::new(
+            "run_number_mismatch",
+            &path.join(folder_layout.run_parameters_file()),
+            &format!(
+                "Run number {} differs from run number {} in RunInfo.xml",
+                run_params.run_number, run_info.run_number
+            ),
+        ));
+    }
+
+    info!(
+        logger,
+        "Checking for cycle directories and base call files..."
+    );
+    let num_cycles: i32 = run_info.reads.iter().map(|read| read.num_cycles).sum();
+    let base_calls = path.join("Data").join("Intensities").join("BaseCalls");
+    let mut files = Vec::new();
+    for lane_no in 1..=run_info.lane_count {
+        let lane_path = base_calls.join(format!("L{:03}", lane_no));
+        if lane_path.is_dir() {
+            collect_lane_files(
+                folder_layout,
+                &run_info,
+                lane_no,
+                &lane_path,
+                num_cycles,
+                &mut files,
+                &mut problems,
+            );
+        } else {
+            problems.push(Problem::new(
+                "missing_lane_dir",
+                &lane_path,
+                "Lane directory is missing",
+            ));
+        }
+    }
+
+    info!(logger, "Checking {} base call files...", files.len());
+    let file_problems = files
+        .par_iter()
+        .map(|file| check_file(logger, file))
+        .collect::<Vec<Vec<Problem>>>();
+    problems.extend(file_problems.into_iter().flatten());
+
+    problems
+}
+
+/// Main entry point for the `validate` command.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client validate");
+    info!(logger, "Options: {:?}", settings);
+
+    let path = Path::new(&settings.validate.path);
+    let problems = validate_folder(logger, path);
+
+    let output = match settings.validate.format.as_ref() {
+        "json" => {
+            serde_json::to_string_pretty(&problems).chain_err(|| "Problem converting to JSON")?
+        }
+        "yaml" => serde_yaml::to_string(&problems).chain_err(|| "Problem converting to YAML")?,
+        format => bail!("Invalid output format: {}", format),
+    };
+    println!("{}", output);
+
+    if !problems.is_empty() {
+        bail!("Found {} problem(s) in {:?}", problems.len(), path);
+    }
+    info!(logger, "No problems found in {:?}", path);
+
+    Ok(())
+}
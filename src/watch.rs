@@ -0,0 +1,253 @@
+//! Implementation of the `watch` command.
+//!
+//! The root directories are scanned periodically for flow cell directories.  A flow cell
+//! directory is (re-)ingested when it appears or when one of the marker files, the set of cycle
+//! directories, or the InterOp files change.  Termination via `SIGTERM` or `SIGINT` is handled
+//! gracefully: the folders currently being processed are finished before shutting down.
+//!
+//! Stalled runs do not change, so they are processed once more when the stall timeout is
+//! reached.
+
+use glob::glob;
+use libc;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::errors::*;
+use ingest;
+use ingest::{cache, discover};
+use settings::Settings;
+
+/// Files whose creation or modification triggers ingesting a flow cell directory.
+static MARKER_FILES: &[&str] = &[
+    "RunInfo.xml",
+    "RTAComplete.txt",
+    "CopyComplete.txt",
+    "RunCompletionStatus.xml",
+];
+
+/// Set by the signal handler when termination was requested.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Signal handler for `SIGTERM` and `SIGINT`, only flags the shutdown request.
+extern "C" fn handle_signal(_signum: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Install `handle_signal()` for `SIGTERM` and `SIGINT`.
+///
+/// `SA_RESTART` is set such that interrupted system calls of the worker threads are restarted.
+fn install_signal_handlers() -> Result<()> {
+    let handler = handle_signal as extern "C" fn(libc::c_int);
+    for signum in &[libc::SIGTERM, libc::SIGINT] {
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(*signum, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            bail!(
+                "Problem installing handler for signal {}: {}",
+                signum,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Return whether termination was requested.
+fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Snapshot of the parts of a flow cell directory that are relevant for ingesting.
+#[derive(Debug, Clone, PartialEq)]
+struct FolderSnapshot {
+    /// Modification times of the `MARKER_FILES`, `None` if missing.
+    marker_mtimes: Vec<Option<SystemTime>>,
+    /// Cycle directories found for all lanes.
+    cycle_dirs: Vec<PathBuf>,
+    /// The `InterOp/*.bin` files with their modification times.
+    interop_mtimes: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl FolderSnapshot {
+    /// Take snapshot of flow cell directory at `path`.
+    fn take(path: &Path) -> Self {
+        let marker_mtimes = MARKER_FILES
+            .iter()
+            .map(|name| {
+                fs::metadata(path.join(name))
+                    .and_then(|m| m.modified())
+                    .ok()
+            })
+            .collect();
+
+        let pattern = format!("{}/Data/Intensities/BaseCalls/L*/C*.1", path.display());
+        let mut cycle_dirs = glob(&pattern)
+            .map(|paths| paths.filter_map(|p| p.ok()).collect::<Vec<PathBuf>>())
+            .unwrap_or_default();
+        cycle_dirs.sort();
+
+        // NextSeq/MiniSeq folders do not have cycle directories, their progress only shows in
+        // the InterOp files.
+        let pattern = format!("{}/InterOp/*.bin", path.display());
+        let mut interop_mtimes = glob(&pattern)
+            .map(|paths| {
+                paths
+                    .filter_map(|p| p.ok())
+                    .map(|p| {
+                        let mtime = fs::metadata(&p).and_then(|m| m.modified()).ok();
+                        (p, mtime)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        interop_mtimes.sort();
+
+        FolderSnapshot {
+            marker_mtimes,
+            cycle_dirs,
+            interop_mtimes,
+        }
+    }
+}
+
+/// Return flow cell directories in `roots` when not searching recursively.
+///
+/// A root is either a flow cell directory itself or a directory containing flow cell
+/// directories.  Flow cell directories are recognized by their `RunInfo.xml` file.
+fn find_run_folders(logger: &slog::Logger, roots: &[String]) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for root in roots {
+        let root = Path::new(root);
+        if root.join("RunInfo.xml").exists() {
+            result.push(root.to_path_buf());
+            continue;
+        }
+        match fs::read_dir(root) {
+            Ok(entries) => {
+                let mut folders = entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir() && path.join("RunInfo.xml").exists())
+                    .collect::<Vec<PathBuf>>();
+                folders.sort();
+                result.append(&mut folders);
+            }
+            Err(e) => warn!(logger, "Could not list root directory {:?}: {}", root, e),
+        }
+    }
+    result
+}
+
+/// Sleep for `seconds` seconds, returning early when termination was requested.
+fn sleep_interruptible(seconds: u64) {
+    for _ in 0..seconds {
+        if shutdown_requested() {
+            return;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Main entry point for the `watch` command.
+///
+/// Runs until terminated by a signal.  Folders for which processing failed are retried with the
+/// next scan.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client watch");
+    info!(logger, "Options: {:?}", settings);
+
+    // Bail out in case of missing project UUID.
+    if settings.ingest.project_uuid.is_empty() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.watch.poll_interval == 0 {
+        bail!("The poll interval must be positive");
+    }
+
+    // Setting number of threads to use in Rayon.
+    debug!(logger, "Using {} threads", settings.threads);
+    env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
+
+    install_signal_handlers()?;
+    let cache = Mutex::new(cache::load_state_cache(logger, settings));
+
+    // Snapshots of the folders at the time they were last processed successfully.
+    let mut snapshots: HashMap<PathBuf, FolderSnapshot> = HashMap::new();
+    // Folders that were processed after their run stalled.
+    let mut stalled: HashSet<PathBuf> = HashSet::new();
+
+    while !shutdown_requested() {
+        debug!(logger, "Scanning root directories...");
+        let folders = if settings.ingest.discover {
+            discover::discover_run_folders(logger, &settings.watch.roots, settings)
+                .chain_err(|| "Problem searching for flow cell directories")?
+        } else {
+            find_run_folders(logger, &settings.watch.roots)
+        };
+        snapshots.retain(|path, _| folders.contains(path));
+        stalled.retain(|path| folders.contains(path));
+
+        // Take the snapshots before processing so changes during processing are picked up with
+        // the next scan.
+        let changed = folders
+            .into_iter()
+            .map(|path| {
+                let snapshot = FolderSnapshot::take(&path);
+                (path, snapshot)
+            })
+            .filter(|(path, snapshot)| {
+                let unchanged = snapshots.get(path) == Some(snapshot);
+                let newly_stalled =
+                    unchanged && !stalled.contains(path) && ingest::is_stalled(path, settings);
+                if newly_stalled {
+                    debug!(logger, "Run in {:?} stalled", path);
+                } else if unchanged {
+                    debug!(logger, "No relevant changes in {:?}", path);
+                }
+                !unchanged || newly_stalled
+            })
+            .collect::<Vec<(PathBuf, FolderSnapshot)>>();
+
+        // Process in chunks of the number of jobs so termination requests are honoured timely.
+        for chunk in changed.chunks(cmp::max(1, settings.ingest.jobs)) {
+            if shutdown_requested() {
+                break;
+            }
+            let paths = chunk
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<PathBuf>>();
+            let succeeded = ingest::process_folders(logger, &paths, &cache, settings);
+            for ((path, snapshot), ok) in chunk.iter().zip(succeeded) {
+                if ok {
+                    snapshots.insert(path.clone(), snapshot.clone());
+                    if ingest::is_stalled(path, settings) {
+                        stalled.insert(path.clone());
+                    } else {
+                        stalled.remove(path);
+                    }
+                } else {
+                    info!(logger, "Will retry folder {:?} with next scan", path);
+                }
+            }
+        }
+
+        sleep_interruptible(settings.watch.poll_interval);
+    }
+
+    info!(logger, "Termination requested, stopping to watch.");
+    Ok(())
+}